@@ -0,0 +1,134 @@
+//! `cmk doctor`: check that the external tools cmk shells out to are
+//! installed, and report their versions.
+
+use anyhow::{Result, anyhow};
+use cmk_config::{CMakeConfig, linker_executable};
+use cmk_core::style;
+use cmk_project::get_project_root;
+use tokio::process::Command;
+
+use super::status::find_in_path;
+
+struct ToolCheck {
+    name: &'static str,
+    required: bool,
+    version_args: &'static [&'static str],
+}
+
+const TOOLS: &[ToolCheck] = &[
+    ToolCheck {
+        name: "git",
+        required: true,
+        version_args: &["--version"],
+    },
+    ToolCheck {
+        name: "cmake",
+        required: true,
+        version_args: &["--version"],
+    },
+    ToolCheck {
+        name: "ninja",
+        required: true,
+        version_args: &["--version"],
+    },
+    ToolCheck {
+        name: "fzf",
+        required: false,
+        version_args: &["--version"],
+    },
+    ToolCheck {
+        name: "ccache",
+        required: false,
+        version_args: &["--version"],
+    },
+    ToolCheck {
+        name: "clang-tidy",
+        required: false,
+        version_args: &["--version"],
+    },
+    ToolCheck {
+        name: "clang-format",
+        required: false,
+        version_args: &["--version"],
+    },
+];
+
+/// First output line that carries a version number, e.g. `cmake version
+/// 3.30.2`. clang-tidy leads with an `LLVM (http://llvm.org/):` banner.
+async fn probe(name: &str, version_args: &[&str]) -> Option<String> {
+    let output = Command::new(name).args(version_args).output().await.ok()?;
+    let text = if output.stdout.is_empty() {
+        output.stderr
+    } else {
+        output.stdout
+    };
+    let text = String::from_utf8_lossy(&text);
+    let line = text
+        .lines()
+        .map(str::trim)
+        .find(|line| line.chars().any(|c| c.is_ascii_digit()))
+        .unwrap_or("found");
+    Some(line.to_string())
+}
+
+/// Warn (without failing) when the executable behind `-fuse-ld=<linker>`
+/// is not on `PATH`.
+pub(crate) fn warn_if_linker_missing(linker: &str) {
+    let executable = linker_executable(linker);
+    if find_in_path(&executable).is_none() {
+        eprintln!(
+            "warning: linker {linker} ({executable}) not found on PATH; linking will fail until it is installed"
+        );
+    }
+}
+
+pub async fn run() -> Result<()> {
+    // The `[cmake] linker` of the current project, if any.
+    let linker = match get_project_root().await {
+        Ok(root) => CMakeConfig::load(&root)
+            .ok()
+            .and_then(|config| config.linker)
+            .filter(|linker| !linker.is_empty())
+            .map(|linker| linker_executable(&linker)),
+        Err(_) => None,
+    };
+    let width = TOOLS
+        .iter()
+        .map(|t| t.name.len())
+        .chain(linker.as_ref().map(String::len))
+        .max()
+        .unwrap_or(0);
+
+    let mut missing = Vec::new();
+    for tool in TOOLS {
+        let kind = if tool.required {
+            "required"
+        } else {
+            "optional"
+        };
+        let (mark, detail) = match probe(tool.name, tool.version_args).await {
+            Some(version) => (style::green("✓").to_string(), version),
+            None if tool.required => {
+                missing.push(tool.name);
+                (
+                    style::red("✗").to_string(),
+                    style::red("not found").to_string(),
+                )
+            }
+            None => (style::yellow("✗").to_string(), "not found".to_string()),
+        };
+        println!("{mark} {name:<width$}  {kind}  {detail}", name = tool.name);
+    }
+    if let Some(linker) = &linker {
+        let (mark, detail) = match probe(linker, &["--version"]).await {
+            Some(version) => (style::green("✓").to_string(), version),
+            None => (style::yellow("✗").to_string(), "not found".to_string()),
+        };
+        println!("{mark} {linker:<width$}  linker    {detail}");
+    }
+
+    if !missing.is_empty() {
+        return Err(anyhow!("Missing required tools: {}", missing.join(", ")));
+    }
+    Ok(())
+}
@@ -0,0 +1,131 @@
+//! Per-target argument history for `cmk run`, kept in
+//! `$XDG_STATE_HOME/cmk/run-history.json` (typically
+//! `~/.local/state/cmk/`).
+
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result, anyhow};
+use serde::{Deserialize, Serialize};
+
+use crate::cmd::shell_quote;
+
+/// How many argument lists are kept per target.
+const MAX_ENTRIES: usize = 50;
+
+/// Project root -> target -> argument lists, most recent first.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub(crate) struct RunHistory {
+    projects: BTreeMap<String, BTreeMap<String, Vec<Vec<String>>>>,
+}
+
+fn history_path() -> Result<PathBuf> {
+    let base = dirs::state_dir()
+        .or_else(|| dirs::home_dir().map(|home| home.join(".local/state")))
+        .ok_or_else(|| anyhow!("cannot resolve $XDG_STATE_HOME / $HOME"))?;
+    Ok(base.join("cmk").join("run-history.json"))
+}
+
+impl RunHistory {
+    /// The saved history, or an empty one if none was written yet.
+    pub(crate) fn load() -> Result<Self> {
+        let path = history_path()?;
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let content = std::fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        serde_json::from_str(&content)
+            .with_context(|| format!("Failed to parse {}", path.display()))
+    }
+
+    pub(crate) fn save(&self) -> Result<()> {
+        let path = history_path()?;
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(&path, serde_json::to_string_pretty(self)?)
+            .with_context(|| format!("Failed to write {}", path.display()))
+    }
+
+    pub(crate) fn entries(&self, project_root: &Path, target: &str) -> &[Vec<String>] {
+        self.projects
+            .get(project_root.to_string_lossy().as_ref())
+            .and_then(|targets| targets.get(target))
+            .map_or(&[], Vec::as_slice)
+    }
+
+    /// Move `args` to the front of `target`'s history, dropping the oldest
+    /// entries past [`MAX_ENTRIES`].
+    pub(crate) fn record(&mut self, project_root: &Path, target: &str, args: &[String]) {
+        let entries = self
+            .projects
+            .entry(project_root.to_string_lossy().to_string())
+            .or_default()
+            .entry(target.to_string())
+            .or_default();
+        entries.retain(|entry| entry != args);
+        entries.insert(0, args.to_vec());
+        entries.truncate(MAX_ENTRIES);
+    }
+}
+
+/// `args` as one shell-style line, single-quoting arguments that need it.
+pub(crate) fn display_args(args: &[String]) -> String {
+    args.iter()
+        .map(|arg| shell_quote(arg))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|a| a.to_string()).collect()
+    }
+
+    #[test]
+    fn record_moves_repeats_to_the_front() {
+        let root = Path::new("/p");
+        let mut history = RunHistory::default();
+        history.record(root, "app", &args(&["-v"]));
+        history.record(root, "app", &args(&["--port", "80"]));
+        history.record(root, "app", &args(&["-v"]));
+        assert_eq!(
+            history.entries(root, "app"),
+            [args(&["-v"]), args(&["--port", "80"])]
+        );
+        assert!(history.entries(root, "other").is_empty());
+        assert!(history.entries(Path::new("/q"), "app").is_empty());
+    }
+
+    #[test]
+    fn record_keeps_at_most_max_entries() {
+        let root = Path::new("/p");
+        let mut history = RunHistory::default();
+        for i in 0..MAX_ENTRIES + 5 {
+            history.record(root, "app", &[i.to_string()]);
+        }
+        let entries = history.entries(root, "app");
+        assert_eq!(entries.len(), MAX_ENTRIES);
+        assert_eq!(entries[0], [(MAX_ENTRIES + 4).to_string()]);
+        assert_eq!(entries[MAX_ENTRIES - 1], ["5"]);
+    }
+
+    #[test]
+    fn display_args_quotes_when_needed() {
+        assert_eq!(
+            display_args(&args(&["--port=80", "a/b.txt"])),
+            "--port=80 a/b.txt"
+        );
+        assert_eq!(
+            display_args(&args(&["hello world", "", "it's"])),
+            r"'hello world' '' 'it'\''s'"
+        );
+        assert_eq!(display_args(&[]), "");
+    }
+}
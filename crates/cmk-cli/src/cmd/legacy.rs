@@ -1,61 +1,111 @@
 use std::{
-    collections::HashMap,
-    num::NonZero,
+    collections::{BTreeMap, HashMap},
+    io::IsTerminal,
     path::{Path, PathBuf},
     process::Stdio,
     sync::Arc,
+    time::{Duration, Instant},
 };
 
 use anyhow::{Context, Result, anyhow};
-use cmk_config::{FmtConfig, LintConfig};
-use cmk_core::{completing_read, confirm};
-use cmk_pkg::{CpmInfo, PackageIndex};
+use cmk_config::{BenchConfig, CMakeConfig, CmkConfig, FmtConfig, LintConfig};
+use cmk_core::{
+    ChildFailed, TimedOut, UserAbort, completing_read, completing_read_with_preview, confirm, style,
+};
+use cmk_pkg::{CpmInfo, MergePreference, PackageIndex};
 use cmk_project::{
-    CMakeProject, Target,
+    BuildOutput, CMakeProject, StdinMode, Target,
     cmake_ast::{CMakeFile, CpmInsertion, render_uri_as_keyword},
-    default::load_template,
-    get_project_root,
+    default::{DEFAULT_CPM_PACKAGES, ScaffoldOptions, load_template},
+    get_project_root, graph, is_ninja_build_dir, resolve_job_count,
 };
+use regex::Regex;
 use tokio::process::Command;
 
-pub(crate) fn get_default_jobs() -> usize {
-    std::env::var("CMK_DEFAULT_JOBS")
-        .ok()
-        .and_then(|s| s.parse::<usize>().ok())
-        .unwrap_or_else(|| {
-            std::thread::available_parallelism()
-                .unwrap_or(NonZero::new(2).unwrap())
-                .get()
-                - 1
-        })
-}
+use crate::cmd::history::{RunHistory, display_args};
+use crate::{ArgsSplitArg, BenchFormatArg, LinkerArg, PreferArg, SanitizerArg};
 
 // ========== Add command ==========
 
-pub(crate) async fn exec_add(name: String, project: bool) -> Result<()> {
+pub(crate) async fn exec_add(
+    name: String,
+    project: bool,
+    inject: bool,
+    alias: Option<String>,
+    pre_release: bool,
+    mut force: bool,
+    checksum: bool,
+) -> Result<()> {
     let pkg_info_path = cmk_pkg::pkg_index_path()?;
     let mut index = PackageIndex::load_or_create(&pkg_info_path)?;
     let (owner, repo) = name
         .split_once('/')
         .with_context(|| "Invalid package name")?;
-    index.add_repo(owner, repo).await?;
+    // Ask before clobbering an alias, but only when someone can answer.
+    if !force
+        && std::io::stdin().is_terminal()
+        && let Some(existing) = index.alias_conflict(alias.as_deref().unwrap_or(repo), &name)
+    {
+        let prompt = format!(
+            "Alias {} points at {existing}. Replace it with {name}?",
+            alias.as_deref().unwrap_or(repo)
+        );
+        if !confirm(&prompt).await? {
+            return Err(UserAbort.into());
+        }
+        force = true;
+    }
+    index
+        .add_repo(owner, repo, alias.as_deref(), pre_release, force, checksum)
+        .await?;
     let tag = index.get_release(&format!("{owner}/{repo}"))?.to_string();
     index.save(&pkg_info_path)?;
 
-    if project {
-        insert_cpm_into_cmakelists(owner, repo, &tag).await?;
+    if project || inject {
+        let project_root = get_project_root().await?;
+        let path = if inject {
+            nearest_cmakelists(&project_root)?
+        } else {
+            project_root.join("CMakeLists.txt")
+        };
+        insert_cpm_into_cmakelists(&path, owner, repo, &tag)?;
     }
     Ok(())
 }
 
-async fn insert_cpm_into_cmakelists(owner: &str, repo: &str, tag: &str) -> Result<()> {
-    let project_root = get_project_root().await?;
-    let path = project_root.join("CMakeLists.txt");
+/// The first `CMakeLists.txt` found walking up from the current directory,
+/// stopping at `project_root`.
+fn nearest_cmakelists(project_root: &Path) -> Result<PathBuf> {
+    let cwd = std::env::current_dir()?;
+    let mut dir = if cwd.starts_with(project_root) {
+        cwd.as_path()
+    } else {
+        project_root
+    };
+    loop {
+        let path = dir.join("CMakeLists.txt");
+        if path.is_file() {
+            return Ok(path);
+        }
+        match dir.parent() {
+            Some(parent) if dir != project_root => dir = parent,
+            _ => {
+                return Err(anyhow!(
+                    "No CMakeLists.txt between {} and {}",
+                    cwd.display(),
+                    project_root.display()
+                ));
+            }
+        }
+    }
+}
+
+fn insert_cpm_into_cmakelists(path: &Path, owner: &str, repo: &str, tag: &str) -> Result<()> {
     if !path.exists() {
         return Err(anyhow!("CMakeLists.txt not found at {}", path.display()));
     }
 
-    let mut cmake = CMakeFile::parse_path(&path)?;
+    let mut cmake = CMakeFile::parse_path(path)?;
 
     let already_present = cmake.cpm_calls().iter().any(|c| {
         c.uri.as_ref().is_some_and(|u| {
@@ -65,8 +115,8 @@ async fn insert_cpm_into_cmakelists(owner: &str, repo: &str, tag: &str) -> Resul
         })
     });
     if already_present {
-        println!(
-            "{owner}/{repo} is already present in {}; skipping insert.",
+        eprintln!(
+            "warning: {owner}/{repo} is already present in {}; skipping insert.",
             path.display()
         );
         return Ok(());
@@ -95,29 +145,46 @@ async fn insert_cpm_into_cmakelists(owner: &str, repo: &str, tag: &str) -> Resul
 
 // ========== Get command ==========
 
-pub(crate) async fn exec_get(name: String) -> Result<()> {
+pub(crate) async fn exec_get(name: String, asset: Option<String>) -> Result<()> {
     let pkg_info_path = cmk_pkg::pkg_index_path()?;
     let index = PackageIndex::load_or_create(&pkg_info_path)?;
+    if let Some(asset) = asset {
+        println!("{}", index.get_asset_url(&name, &asset)?);
+        return Ok(());
+    }
     let pkg_name = index.get_pkg_name(&name)?;
     let release = index.get_release(&pkg_name)?;
-    println!("{pkg_name}: {release}");
+    match release.published_at {
+        Some(published_at) => println!(
+            "{pkg_name}: {release} (published {})",
+            published_at.format("%Y-%m-%d")
+        ),
+        None => println!("{pkg_name}: {release}"),
+    }
     Ok(())
 }
 
 // ========== Update command ==========
 
-pub(crate) async fn exec_update(project: bool, yes: bool) -> Result<()> {
+pub(crate) async fn exec_update(project: bool, yes: bool, fail_fast: bool) -> Result<()> {
     let pkg_info_path = cmk_pkg::pkg_index_path()?;
     let mut index = PackageIndex::load_or_create(&pkg_info_path)?;
-    index.update().await?;
+    // Keep the packages that did update even when --fail-fast errors out.
+    let updated = index.update(fail_fast).await;
     index.save(&pkg_info_path)?;
+    updated?;
     let cpm_info_path = cmk_pkg::cpm_info_path()?;
     let old_cpm = CpmInfo::load(&cpm_info_path)?;
     let new_cpm = CpmInfo::query_from_github().await?;
     if old_cpm.version != new_cpm.version {
-        println!("CPM: {} -> {}", old_cpm.version, new_cpm.version);
-        new_cpm.save(cpm_info_path)?;
+        println!(
+            "CPM: {} {} {}",
+            old_cpm.version,
+            style::arrow(),
+            style::green(&new_cpm.version)
+        );
     }
+    new_cpm.save(cpm_info_path)?;
     if project {
         update_project_cmakelists(yes).await?;
     }
@@ -189,7 +256,12 @@ async fn update_project_cmakelists(yes: bool) -> Result<()> {
                 if latest == p.current {
                     continue;
                 }
-                println!("  {pkg}: {} -> {latest}", p.current);
+                println!(
+                    "  {pkg}: {} {} {}",
+                    p.current,
+                    style::arrow(),
+                    style::green(&latest)
+                );
                 edits.push((p.range.clone(), latest));
             }
             Err(e) => eprintln!("  {pkg}: {e:#}"),
@@ -213,9 +285,115 @@ async fn update_project_cmakelists(yes: bool) -> Result<()> {
     Ok(())
 }
 
+// ========== Rollback command ==========
+
+pub(crate) async fn exec_rollback(name: Option<String>) -> Result<()> {
+    let pkg_info_path = cmk_pkg::pkg_index_path()?;
+    let mut index = PackageIndex::load_or_create(&pkg_info_path)?;
+    index.rollback(name.as_deref())?;
+    index.save(&pkg_info_path)?;
+    Ok(())
+}
+
+// ========== Pin command ==========
+
+pub(crate) async fn exec_pin(build: Option<String>) -> Result<()> {
+    let project = CMakeProject::new().await?;
+    let mut versions: Vec<(String, String)> = project
+        .detect_cpm_versions(build.as_deref())
+        .await?
+        .into_iter()
+        .collect();
+    if versions.is_empty() {
+        println!("No CPM package versions found in the cache.");
+        return Ok(());
+    }
+    versions.sort();
+    let pkg_info_path = cmk_pkg::pkg_index_path()?;
+    let mut index = PackageIndex::load_or_create(&pkg_info_path)?;
+    for (name, version) in versions {
+        if index.pin(&name, &version).is_none() {
+            eprintln!("warning: {name} {version} is not in the package index; skipped");
+        }
+    }
+    index.save(&pkg_info_path)?;
+    Ok(())
+}
+
+// ========== Import command ==========
+
+pub(crate) async fn exec_import(path: PathBuf, prefer: Option<PreferArg>) -> Result<()> {
+    let pkg_info_path = cmk_pkg::pkg_index_path()?;
+    let mut index = PackageIndex::load_or_create(&pkg_info_path)?;
+    let content = std::fs::read_to_string(&path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    let other: PackageIndex = serde_json::from_str(&content)
+        .with_context(|| format!("Failed to parse {}", path.display()))?;
+    let (aliases, releases) = (other.aliases.len(), other.releases.len());
+    let prefer = prefer.map(|p| match p {
+        PreferArg::Theirs => MergePreference::Theirs,
+        PreferArg::Mine => MergePreference::Mine,
+    });
+    index.merge(other, prefer)?;
+    index.save(&pkg_info_path)?;
+    println!(
+        "Imported {aliases} aliases and {releases} releases from {}",
+        path.display()
+    );
+    Ok(())
+}
+
+// ========== Alias command ==========
+
+pub(crate) async fn exec_alias_rename(old: String, new: String) -> Result<()> {
+    let pkg_info_path = cmk_pkg::pkg_index_path()?;
+    let mut index = PackageIndex::load_or_create(&pkg_info_path)?;
+    let pkg = index.rename_alias(&old, &new)?.to_string();
+    index.save(&pkg_info_path)?;
+    println!("{new} -> {pkg}");
+    Ok(())
+}
+
 // ========== New command ==========
 
-pub(crate) async fn exec_new(name: String, template: Option<String>) -> Result<()> {
+#[derive(Debug)]
+pub(crate) struct NewOptions {
+    pub name: String,
+    pub template: Option<String>,
+    pub no_git: bool,
+    /// The C++ standard for `{cxx_standard}`.
+    pub std: String,
+    /// Use the cached CPM version and the package index, never GitHub.
+    pub offline: bool,
+    pub cmake_modules: bool,
+    pub no_lock: bool,
+    pub linker: Option<LinkerArg>,
+    /// Leave the template's sanitizer options out.
+    pub no_sanitizers: bool,
+    /// Sanitizers for the template instead of the default ASan + UBSan.
+    pub sanitizers: Vec<SanitizerArg>,
+}
+
+pub(crate) async fn exec_new(options: NewOptions) -> Result<()> {
+    let NewOptions {
+        name,
+        template,
+        no_git,
+        std,
+        offline,
+        cmake_modules,
+        no_lock,
+        linker,
+        no_sanitizers,
+        sanitizers,
+    } = options;
+    let mut sanitizer_names: Vec<&str> = Vec::new();
+    for sanitizer in sanitizers {
+        if !sanitizer_names.contains(&sanitizer.name()) {
+            sanitizer_names.push(sanitizer.name());
+        }
+    }
+    check_sanitizer_combination(&sanitizer_names)?;
     let path = Path::new(&name);
     if path.try_exists()? {
         return Err(anyhow!("{} already exists", name));
@@ -226,82 +404,557 @@ pub(crate) async fn exec_new(name: String, template: Option<String>) -> Result<(
     std::fs::create_dir_all(path)?;
     std::env::set_current_dir(path)?;
 
-    Command::new("git").arg("init").spawn()?.wait().await?;
+    if !no_git {
+        match Command::new("git").arg("init").spawn() {
+            Ok(mut child) => {
+                child.wait().await?;
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                eprintln!("warning: git not found; skipping `git init` (pass --no-git to silence)");
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
 
     let cpm_info_path = cmk_pkg::cpm_info_path()?;
-    let info = if let Ok(info) = CpmInfo::load(&cpm_info_path) {
-        info
-    } else {
-        let parent = cpm_info_path
-            .parent()
-            .with_context(|| "Failed to get parent directory of cpm.json")?;
-        std::fs::create_dir_all(parent)?;
-        let info = CpmInfo::query_from_github().await?;
-        info.save(&cpm_info_path)?;
-        info
+    let info = match CpmInfo::load(&cpm_info_path).ok() {
+        Some(info) if offline || !info.is_stale() => info,
+        None if offline => {
+            return Err(anyhow!(
+                "No cached CPM version at {}; run `cmk new` once without --offline",
+                cpm_info_path.display()
+            ));
+        }
+        cached => {
+            let parent = cpm_info_path
+                .parent()
+                .with_context(|| "Failed to get parent directory of cpm.json")?;
+            std::fs::create_dir_all(parent)?;
+            match CpmInfo::query_from_github().await {
+                Ok(info) => {
+                    info.save(&cpm_info_path)?;
+                    info
+                }
+                Err(e) => match cached {
+                    Some(info) => {
+                        eprintln!(
+                            "warning: could not refresh the CPM version ({e:#}); using cached {}",
+                            info.version
+                        );
+                        info
+                    }
+                    None => return Err(e),
+                },
+            }
+        }
     };
 
     let mut vars = HashMap::new();
     vars.insert("{name}", name.as_str());
     vars.insert("{cpm_version}", info.version.as_str());
     vars.insert("{cpm_hash_sum}", info.sha256.as_str());
+    vars.insert("{cxx_standard}", std.as_str());
+    let versions = default_package_versions(offline).await?;
+    for (placeholder, version) in &versions {
+        vars.insert(placeholder.as_str(), version.as_str());
+    }
 
+    if let Some(linker) = linker {
+        super::doctor::warn_if_linker_missing(linker.name());
+    }
+    let options = ScaffoldOptions {
+        gitignore: !no_git,
+        cmake_modules,
+        package_lock: !no_lock,
+        linker: linker.map(|linker| linker.name().to_string()),
+        sanitizers: if no_sanitizers {
+            Vec::new()
+        } else if sanitizer_names.is_empty() {
+            ScaffoldOptions::default().sanitizers
+        } else {
+            sanitizer_names
+                .iter()
+                .map(|name| name.to_string())
+                .collect()
+        },
+    };
     let project_dir = std::env::current_dir()?;
-    template.apply(&project_dir, &vars)?;
+    template.apply(&project_dir, &vars, &options)?;
 
     Ok(())
 }
 
+/// `{<repo>_version}` placeholder and version of each [`DEFAULT_CPM_PACKAGES`]
+/// entry: the release recorded in the package index when there is one, else
+/// (unless `offline`) the latest release on GitHub. The index is only read,
+/// never updated.
+async fn default_package_versions(offline: bool) -> Result<Vec<(String, String)>> {
+    let index_path = cmk_pkg::pkg_index_path()?;
+    let index = if index_path.try_exists()? {
+        Some(PackageIndex::load_or_create(&index_path)?)
+    } else {
+        None
+    };
+    let mut versions = Vec::with_capacity(DEFAULT_CPM_PACKAGES.len());
+    for (pkg, fallback) in DEFAULT_CPM_PACKAGES {
+        let (owner, repo) = pkg.split_once('/').unwrap_or((pkg, pkg));
+        let indexed = index
+            .as_ref()
+            .and_then(|index| index.get_release(pkg).ok())
+            .map(|release| release.tag.clone());
+        let version = match indexed {
+            Some(tag) => tag,
+            None if offline => fallback.to_string(),
+            None => match cmk_pkg::query_latest_tag(owner, repo).await {
+                Ok(Some(tag)) => tag,
+                Ok(None) => fallback.to_string(),
+                Err(e) => {
+                    eprintln!("warning: {e:#}; using {pkg} {fallback}");
+                    fallback.to_string()
+                }
+            },
+        };
+        versions.push((format!("{{{repo}_version}}"), version));
+    }
+    Ok(versions)
+}
+
+// ========== Output format ==========
+
+/// How `build` and `run` report their outcome.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum OutputFormat {
+    Human,
+    /// One JSON object on the last line of stdout, for CI wrappers.
+    Json,
+}
+
+impl OutputFormat {
+    /// Turn the result of `build`/`run` on a target into this format's report.
+    /// In JSON mode a failure is printed as `{"success": false, ...}` and the
+    /// process exits with [`exit_code`] (the child's own code, or 124 for a
+    /// timeout) instead of printing a second, human error.
+    fn report(self, result: Result<String>, start: Instant) -> Result<()> {
+        if self == OutputFormat::Human {
+            return result.map(|_| ());
+        }
+        match result {
+            Ok(target) => {
+                let report = serde_json::json!({
+                    "success": true,
+                    "exit_code": 0,
+                    "duration_ms": start.elapsed().as_millis() as u64,
+                    "target": target,
+                });
+                println!("{report}");
+                Ok(())
+            }
+            Err(e) => {
+                let report = serde_json::json!({
+                    "success": false,
+                    "error": format!("{e:#}"),
+                });
+                println!("{report}");
+                std::process::exit(exit_code(&e))
+            }
+        }
+    }
+}
+
+/// The exit code cmk reports for a failed command: the child's own code, 124
+/// for a timeout, 1 otherwise.
+pub(crate) fn exit_code(e: &anyhow::Error) -> i32 {
+    if e.is::<TimedOut>() {
+        124
+    } else {
+        e.downcast_ref::<ChildFailed>()
+            .map_or(1, ChildFailed::exit_code)
+    }
+}
+
 // ========== Run command ==========
 
-pub(crate) async fn exec_run(
+#[derive(Debug, Default)]
+pub(crate) struct RunOptions {
+    pub target: Option<String>,
+    pub args: Vec<String>,
+    pub build: Option<String>,
+    /// Use the build dir with this CMAKE_BUILD_TYPE instead of `build`.
+    pub build_type: Option<&'static str>,
+    /// Only offer targets under this `FOLDER` in the interactive picker.
+    pub folder: Option<String>,
+    /// Pick `args` from the target's argument history.
+    pub history: bool,
+    pub timeout: Option<Duration>,
+    /// Close the target's stdin instead of forwarding cmk's.
+    pub no_stdin: bool,
+    /// Arguments to put before `args`, read with [`read_args_file`].
+    pub args_file: Option<PathBuf>,
+    pub args_split: ArgsSplitArg,
+}
+
+/// Arguments listed in `path`, one per line or per word. Blank lines and
+/// `#` comment lines are skipped.
+fn read_args_file(path: &Path, split: ArgsSplitArg) -> Result<Vec<String>> {
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    let lines = content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'));
+    Ok(match split {
+        ArgsSplitArg::Lines => lines.map(str::to_string).collect(),
+        ArgsSplitArg::Words => lines
+            .flat_map(str::split_whitespace)
+            .map(str::to_string)
+            .collect(),
+    })
+}
+
+pub(crate) async fn exec_run(options: RunOptions, format: OutputFormat) -> Result<()> {
+    let start = Instant::now();
+    let result = run_selected_target(options).await;
+    format.report(result, start)
+}
+
+/// The executable target `cmk run` (and `cmk which`) means by `target`:
+/// an exact name, else a fuzzy match, else a pick from those in `folder`.
+async fn select_executable_target(
+    project: &CMakeProject,
+    build: &str,
     target: Option<String>,
-    args: Vec<String>,
-    build: Option<String>,
-) -> Result<()> {
-    let project = CMakeProject::new().await?;
-    let targets = project.collect_executable_targets(build.as_deref()).await?;
+    folder: Option<&str>,
+) -> Result<Target> {
+    let targets = project.collect_executable_targets(Some(build)).await?;
     if targets.is_empty() {
         return Err(anyhow!("Exectuable targets not fount"));
     }
-    let targets: HashMap<String, Target> = targets
+    let last = project.last_target(build);
+    let build_dir = project.get_build_dir(build)?;
+    let mut targets: HashMap<String, Target> = targets
         .into_iter()
         .map(|target| (target.name.clone(), target))
         .collect();
-    let target = if let Some(target) = target {
-        targets
-            .get(&target)
-            .with_context(|| format!("Target {target} not found"))?
+    let target_name = match target {
+        Some(name) if targets.contains_key(&name) => name,
+        Some(name) => {
+            let names: Vec<String> = targets.keys().cloned().collect();
+            let matches = fuzzy_matches(&name, &names);
+            if matches.is_empty() {
+                return Err(anyhow!("Target {name} not found"));
+            }
+            pick_run_target(matches, &targets, last.as_deref(), build_dir).await?
+        }
+        None => {
+            let names: Vec<String> = targets
+                .values()
+                .filter(|t| folder.is_none_or(|f| t.in_folder(f)))
+                .map(|t| t.name.clone())
+                .collect();
+            if names.is_empty() {
+                return Err(anyhow!("No executable targets in the selected folder"));
+            }
+            pick_run_target(names, &targets, last.as_deref(), build_dir).await?
+        }
+    };
+    targets
+        .remove(&target_name)
+        .with_context(|| format!("Target {target_name} not found"))
+}
+
+/// Resolve and run the target; returns its name for the result report.
+async fn run_selected_target(options: RunOptions) -> Result<String> {
+    let RunOptions {
+        target,
+        mut args,
+        build,
+        build_type,
+        folder,
+        history,
+        timeout,
+        no_stdin,
+        args_file,
+        args_split,
+    } = options;
+    if let Some(path) = &args_file {
+        let mut file_args = read_args_file(path, args_split)?;
+        file_args.append(&mut args);
+        args = file_args;
+    }
+    let project = CMakeProject::new().await?;
+    let build = match build_type {
+        Some(build_type) => project.build_dir_for_type(build_type)?,
+        None => project.resolve_build_dir_name(build.as_deref()).await?,
+    };
+    let target = select_executable_target(&project, &build, target, folder.as_deref()).await?;
+    let target_name = target.name.clone();
+    if let Err(e) = project.remember_target(&build, &target_name) {
+        eprintln!("warning: {e:#}");
+    }
+    // The history is a convenience; a broken file must not stop the run.
+    let mut run_history = RunHistory::load().unwrap_or_else(|e| {
+        eprintln!("warning: {e:#}; starting a new run history");
+        RunHistory::default()
+    });
+    if history {
+        let entries = run_history.entries(&project.project_root, &target_name);
+        if entries.is_empty() {
+            return Err(anyhow!("No recorded arguments for {target_name}"));
+        }
+        let lines: Vec<String> = entries.iter().map(|args| display_args(args)).collect();
+        let line = completing_read(&lines, None).await?;
+        let i = lines
+            .iter()
+            .position(|l| *l == line)
+            .with_context(|| "No arguments selected")?;
+        args = entries[i].clone();
+    }
+    let stdin = if no_stdin {
+        StdinMode::Null
     } else {
-        let target_names = targets.keys().map(|s| s.to_string()).collect::<Vec<_>>();
-        if target_names.len() == 1 {
-            targets
-                .get(&target_names[0])
-                .with_context(|| format!("Target {} not found", target_names[0]))?
-        } else {
-            let target_name = completing_read(&target_names).await?;
-            if target_name.is_empty() {
-                return Err(anyhow!("No target selected"));
+        StdinMode::Inherit
+    };
+    project
+        .run_target(&target, &args, Some(&build), stdin, timeout)
+        .await?;
+    if !args.is_empty() {
+        run_history.record(&project.project_root, &target_name, &args);
+        if let Err(e) = run_history.save() {
+            eprintln!("warning: {e:#}");
+        }
+    }
+    Ok(target_name)
+}
+
+// ========== Bench command ==========
+
+pub(crate) async fn exec_bench(
+    build: Option<String>,
+    target: Option<String>,
+    filter: Option<String>,
+    format: Option<BenchFormatArg>,
+    out: Option<PathBuf>,
+    mut args: Vec<String>,
+) -> Result<()> {
+    let project = CMakeProject::new().await?;
+    let build = project.resolve_build_dir_name(build.as_deref()).await?;
+    let patterns = BenchConfig::load(&project.project_root)?.patterns();
+    let targets: HashMap<String, Target> = project
+        .find_benchmark_targets(Some(&build), &patterns)
+        .await?
+        .into_iter()
+        .map(|target| (target.name.clone(), target))
+        .collect();
+    if targets.is_empty() {
+        return Err(anyhow!(
+            "No benchmark targets (named {}) found",
+            patterns.join(" or ")
+        ));
+    }
+    let names: Vec<String> = targets.keys().cloned().collect();
+    let candidates = match target {
+        Some(name) if targets.contains_key(&name) => vec![name],
+        Some(name) => {
+            let matches = fuzzy_matches(&name, &names);
+            if matches.is_empty() {
+                return Err(anyhow!("Benchmark target {name} not found"));
             }
-            targets
-                .get(&target_name)
-                .with_context(|| format!("Target {target_name} not found"))?
+            matches
         }
+        None => names,
     };
-    project.run_target(target, &args, None).await?;
-    Ok(())
+    let last = project.last_target(&build);
+    let target_name = pick_target(candidates, &targets, last.as_deref()).await?;
+
+    let mut bench_args = Vec::new();
+    if let Some(filter) = filter {
+        bench_args.push(format!("--benchmark_filter={filter}"));
+    }
+    if let Some(format) = format {
+        bench_args.push(format!("--benchmark_format={}", format.name()));
+    }
+    if let Some(out) = out {
+        bench_args.push(format!("--benchmark_out={}", out.display()));
+        if let Some(format) = format {
+            bench_args.push(format!("--benchmark_out_format={}", format.name()));
+        }
+    }
+    bench_args.append(&mut args);
+    project
+        .run_target(
+            &targets[&target_name],
+            &bench_args,
+            Some(&build),
+            StdinMode::Null,
+            None,
+        )
+        .await
 }
 
-// ========== Build command ==========
+// ========== Which command ==========
 
-pub(crate) async fn exec_build(
+pub(crate) async fn exec_which(
     target: Option<String>,
     build: Option<String>,
-    interactive: bool,
-    jobs: Option<usize>,
+    build_first: bool,
 ) -> Result<()> {
     let project = CMakeProject::new().await?;
-    let build = if let Some(dir) = build {
+    let build = project.resolve_build_dir_name(build.as_deref()).await?;
+    let target = select_executable_target(&project, &build, target, None).await?;
+    if build_first {
+        project
+            .build_target(
+                std::slice::from_ref(&target.name),
+                Some(&build),
+                resolve_job_count(None),
+                BuildOutput::Stderr,
+                &[],
+            )
+            .await?;
+    }
+    let path = target
+        .artifact_path(project.get_build_dir(&build)?)
+        .with_context(|| format!("Target {} has no artifact", target.name))?;
+    println!("{}", path.display());
+    Ok(())
+}
+
+/// Names in `names` matching `query` case-insensitively: those containing it
+/// as a substring, or failing that, as a subsequence (`srv` → `my_server`).
+fn fuzzy_matches(query: &str, names: &[String]) -> Vec<String> {
+    let query = query.to_lowercase();
+    let is_subsequence = |name: &str| {
+        let mut chars = name.chars();
+        query.chars().all(|q| chars.any(|c| c == q))
+    };
+    let mut matches: Vec<String> = names
+        .iter()
+        .filter(|n| n.to_lowercase().contains(&query))
+        .cloned()
+        .collect();
+    if matches.is_empty() {
+        matches = names
+            .iter()
+            .filter(|n| is_subsequence(&n.to_lowercase()))
+            .cloned()
+            .collect();
+    }
+    matches.sort();
+    matches
+}
+
+/// `candidates` sorted, with `last` (the build dir's previously used
+/// target) moved to the front when present.
+fn order_candidates(mut candidates: Vec<String>, last: Option<&str>) -> Vec<String> {
+    candidates.sort();
+    if let Some(i) = last.and_then(|last| candidates.iter().position(|c| c == last)) {
+        let last = candidates.remove(i);
+        candidates.insert(0, last);
+    }
+    candidates
+}
+
+/// The only candidate, or the user's fzf pick among several, ordered by
+/// [`order_candidates`].
+async fn pick_target(
+    candidates: Vec<String>,
+    targets: &HashMap<String, Target>,
+    last: Option<&str>,
+) -> Result<String> {
+    if let [only] = candidates.as_slice() {
+        return Ok(only.clone());
+    }
+    let candidates = order_candidates(candidates, last);
+    let previews: HashMap<String, String> = candidates
+        .iter()
+        .filter_map(|name| Some((name.clone(), target_preview(targets.get(name)?))))
+        .collect();
+    let target_name = completing_read(&candidates, Some(&previews)).await?;
+    if target_name.is_empty() {
+        return Err(anyhow!("No target selected"));
+    }
+    Ok(target_name)
+}
+
+/// [`pick_target`] for `cmk run`. In a Ninja build dir the preview shows
+/// `ninja -t query` for the highlighted target, i.e. what it is built from
+/// and what depends on it, instead of the target summary.
+async fn pick_run_target(
+    candidates: Vec<String>,
+    targets: &HashMap<String, Target>,
+    last: Option<&str>,
+    build_dir: &Path,
+) -> Result<String> {
+    if candidates.len() < 2 || !is_ninja_build_dir(build_dir) {
+        return pick_target(candidates, targets, last).await;
+    }
+    let candidates = order_candidates(candidates, last);
+    let preview = format!(
+        "ninja -C {} -t query {{}}",
+        shell_quote(&build_dir.to_string_lossy())
+    );
+    completing_read_with_preview(&candidates, "target", Some(&preview)).await
+}
+
+// ========== Build command ==========
+
+/// Arguments of `cmk build` (and the bare `cmk` shortcut).
+#[derive(Debug, Default)]
+pub(crate) struct BuildOptions {
+    /// Targets to build in one `cmake --build`; empty means `all`.
+    pub targets: Vec<String>,
+    pub build: Option<String>,
+    /// Use the build dir with this CMAKE_BUILD_TYPE instead of `build`.
+    pub build_type: Option<&'static str>,
+    pub interactive: bool,
+    pub jobs: Option<usize>,
+    pub lsp_symlink: bool,
+    pub toolchain: Option<PathBuf>,
+    /// `-D KEY=VALUE` cache definitions.
+    pub definitions: Vec<String>,
+    /// Only offer targets under this `FOLDER` in the interactive picker.
+    pub folder: Option<String>,
+    /// Show a progress bar instead of Ninja's raw output.
+    pub progress: bool,
+    /// Only (re)configure the build dir; build nothing.
+    pub config_only: bool,
+    /// Build every target whose name matches this regex.
+    pub pattern: Option<String>,
+    /// `--sanitize` list (or `none`), remembered in `.cmk.toml`.
+    pub sanitize: Option<String>,
+    /// Passed to Ninja/Make after `cmake --build ... --`.
+    pub tool_args: Vec<String>,
+}
+
+pub(crate) async fn exec_build(options: BuildOptions, format: OutputFormat) -> Result<()> {
+    let start = Instant::now();
+    format.report(build_selected_target(options).await, start)
+}
+
+/// Resolve the build dir and targets and build them; returns the target
+/// names, space-separated, for the result report (empty with
+/// `config_only`).
+async fn build_selected_target(options: BuildOptions) -> Result<String> {
+    let BuildOptions {
+        targets: names,
+        build,
+        build_type,
+        interactive,
+        jobs,
+        lsp_symlink,
+        toolchain,
+        definitions,
+        folder,
+        progress,
+        config_only,
+        pattern,
+        sanitize,
+        tool_args,
+    } = options;
+    let project = CMakeProject::new().await?;
+    let build = if let Some(build_type) = build_type {
+        project.build_dir_for_type(build_type)?
+    } else if let Some(dir) = build {
         let bp = PathBuf::from(&dir);
         let rp = if bp.is_absolute() {
             bp.strip_prefix(&project.project_root)?.to_owned()
@@ -311,71 +964,621 @@ pub(crate) async fn exec_build(
         };
         rp.to_string_lossy().to_string()
     } else {
-        let dirs = project.list_build_dirs();
-        if dirs.len() == 1 {
-            dirs[0].clone()
-        } else if let Some(k) = project.detect_pwd_key() {
-            k
-        } else if let Some(default) = &project.build_config.default {
-            if !project.build_dirs.contains_key(default) {
-                return Err(anyhow!(
-                    "Configured default build dir '{default}' not found. Known: {:?}",
-                    dirs
-                ));
-            }
-            default.clone()
-        } else {
-            let res = completing_read(&dirs).await?;
-            if res.is_empty() {
-                return Err(anyhow!("No build directory selected"));
-            }
-            res
-        }
+        project.resolve_build_dir_name(None).await?
     };
-    let target = if interactive && target.is_none() {
-        let targets = project.collect_executable_targets(Some(&build)).await?;
+    resolve_sanitizers(&project, sanitize)?;
+    let toolchain = resolve_toolchain_file(&project, toolchain)?;
+    let definitions = resolve_definitions(&project, definitions)?;
+    if config_only {
+        let start = Instant::now();
+        let result = project
+            .configure_with(
+                Some(&build),
+                toolchain.as_deref(),
+                &definitions,
+                None,
+                true,
+                false,
+            )
+            .await?;
+        if result.is_some() {
+            warn_if_configured_linker_missing(&project)?;
+        }
+        if lsp_symlink || project.build_config.lsp_symlink {
+            project.link_compile_commands(Some(&build)).await?;
+        }
+        println!("Configured in {:.1}s", start.elapsed().as_secs_f64());
+        return Ok(String::new());
+    }
+    let targets = if let Some(pattern) = &pattern {
+        let targets = matching_targets(&project, &build, pattern).await?;
+        println!("Building {}", targets.join(", "));
+        targets
+    } else if interactive && names.is_empty() {
+        let mut targets = project.collect_executable_targets(Some(&build)).await?;
+        if let Some(folder) = &folder {
+            targets.retain(|t| t.in_folder(folder));
+        }
         if targets.is_empty() {
             return Err(anyhow!("No buildable targets found"));
         }
         let target_names = targets.iter().map(|t| t.name.clone()).collect::<Vec<_>>();
-        let target_name = completing_read(&target_names).await?;
-        if target_name.is_empty() {
-            return Err(anyhow!("No target selected"));
-        }
-        target_name
+        let targets: HashMap<String, Target> =
+            targets.into_iter().map(|t| (t.name.clone(), t)).collect();
+        vec![
+            pick_target(
+                target_names,
+                &targets,
+                project.last_target(&build).as_deref(),
+            )
+            .await?,
+        ]
+    } else if names.is_empty() {
+        vec!["all".to_string()]
     } else {
-        target.unwrap_or_else(|| "all".to_string())
+        let mut targets = Vec::with_capacity(names.len());
+        for name in names {
+            let target = if BUILTIN_TARGETS.contains(&name.as_str()) {
+                name
+            } else {
+                resolve_build_target(&project, &build, name).await?
+            };
+            if !targets.contains(&target) {
+                targets.push(target);
+            }
+        }
+        targets
     };
+    if let Some(target) = targets.iter().find(|t| *t != "all")
+        && let Err(e) = project.remember_target(&build, target)
+    {
+        eprintln!("warning: {e:#}");
+    }
+    let result = project
+        .configure_with(
+            Some(&build),
+            toolchain.as_deref(),
+            &definitions,
+            None,
+            false,
+            false,
+        )
+        .await?;
+    if result.is_some() {
+        warn_if_configured_linker_missing(&project)?;
+    }
     project
-        .build_target(&target, Some(&build), jobs.unwrap_or_else(get_default_jobs))
+        .build_target(
+            &targets,
+            Some(&build),
+            resolve_job_count(jobs),
+            if progress {
+                BuildOutput::Progress
+            } else {
+                BuildOutput::Passthrough
+            },
+            &tool_args,
+        )
         .await?;
+    if lsp_symlink || project.build_config.lsp_symlink {
+        project.link_compile_commands(Some(&build)).await?;
+    }
+    Ok(targets.join(" "))
+}
+
+/// Every target (of any type) whose whole name matches `pattern`, sorted.
+async fn matching_targets(
+    project: &CMakeProject,
+    build: &str,
+    pattern: &str,
+) -> Result<Vec<String>> {
+    let regex = Regex::new(&format!("^(?:{pattern})$"))
+        .with_context(|| format!("Invalid target pattern {pattern}"))?;
+    let mut targets: Vec<String> = project
+        .collect_all_targets(Some(build))
+        .await?
+        .into_iter()
+        .map(|t| t.name)
+        .filter(|name| regex.is_match(name))
+        .collect();
+    if targets.is_empty() {
+        return Err(anyhow!("No targets match {pattern}"));
+    }
+    targets.sort();
+    targets.dedup();
+    Ok(targets)
+}
+
+/// Targets CMake generates itself, which the File API doesn't list. They are
+/// built as given rather than fuzzy-matched against project targets.
+const BUILTIN_TARGETS: &[&str] = &[
+    "all",
+    "clean",
+    "install",
+    "install/local",
+    "install/strip",
+    "test",
+    "package",
+    "package_source",
+    "edit_cache",
+    "rebuild_cache",
+    "list_install_components",
+    "help",
+];
+
+/// Map a partial target name to a real one via [`fuzzy_matches`]. Names that
+/// are exact or match nothing go to cmake as-is. A single match is only
+/// taken after the user confirms it.
+async fn resolve_build_target(project: &CMakeProject, build: &str, name: String) -> Result<String> {
+    let targets: HashMap<String, Target> = project
+        .collect_all_targets(Some(build))
+        .await
+        .unwrap_or_default()
+        .into_iter()
+        .map(|t| (t.name.clone(), t))
+        .collect();
+    if targets.contains_key(&name) {
+        return Ok(name);
+    }
+    let names: Vec<String> = targets.keys().cloned().collect();
+    let matches = fuzzy_matches(&name, &names);
+    match matches.as_slice() {
+        [] => Ok(name),
+        [only] if !std::io::stdin().is_terminal() => {
+            Err(anyhow!("Target {name} not found; did you mean {only}?"))
+        }
+        [only] => {
+            if !confirm(&format!("Target {name} not found. Build {only}?")).await? {
+                return Err(UserAbort.into());
+            }
+            Ok(only.clone())
+        }
+        _ => pick_target(matches, &targets, project.last_target(build).as_deref()).await,
+    }
+}
+
+// ========== Graph command ==========
+
+pub(crate) async fn exec_graph(build: Option<String>, target: Option<String>) -> Result<()> {
+    let project = CMakeProject::new().await?;
+    let targets = project.collect_all_targets(build.as_deref()).await?;
+    print!("{}", graph::to_dot(&targets, target.as_deref())?);
     Ok(())
 }
 
+// ========== Targets command ==========
+
+/// Whether a File API target type is `wanted`, compared case-insensitively.
+/// `LIBRARY` stands for every `*_LIBRARY` type.
+fn target_type_matches(target_type: &str, wanted: &str) -> bool {
+    target_type.eq_ignore_ascii_case(wanted)
+        || (wanted.eq_ignore_ascii_case("LIBRARY") && target_type.ends_with("_LIBRARY"))
+}
+
+pub(crate) async fn exec_targets(
+    build: Option<String>,
+    target_type: Option<String>,
+    json: bool,
+) -> Result<()> {
+    let project = CMakeProject::new().await?;
+    let build = project.resolve_build_dir_name(build.as_deref()).await?;
+    let build_dir = project.get_build_dir(&build)?;
+    let mut targets = project.collect_all_targets(Some(&build)).await?;
+    if let Some(wanted) = &target_type {
+        targets.retain(|t| target_type_matches(&t.target_type, wanted));
+    }
+    targets.sort_by(|a, b| a.name.cmp(&b.name));
+    if json {
+        let entries: Vec<serde_json::Value> = targets
+            .iter()
+            .map(|target| {
+                let artifacts: Vec<PathBuf> = target
+                    .artifacts
+                    .iter()
+                    .flatten()
+                    .map(|artifact| build_dir.join(&artifact.path))
+                    .collect();
+                serde_json::json!({
+                    "name": target.name,
+                    "type": target.target_type,
+                    "artifacts": artifacts,
+                })
+            })
+            .collect();
+        println!("{}", serde_json::to_string_pretty(&entries)?);
+    } else {
+        for target in &targets {
+            println!("{} ({})", target.name, target.target_type);
+        }
+    }
+    Ok(())
+}
+
+// ========== Builds command ==========
+
+pub(crate) async fn exec_builds() -> Result<()> {
+    let project = CMakeProject::new().await?;
+    let infos = project.build_dir_info();
+    let dash = |v: &Option<String>| v.clone().unwrap_or_else(|| "-".to_string());
+    let name_width = infos.iter().map(|i| i.name.len()).max().unwrap_or(0);
+    let type_width = infos
+        .iter()
+        .map(|i| dash(&i.build_type).len())
+        .max()
+        .unwrap_or(0);
+    let generator_width = infos
+        .iter()
+        .map(|i| dash(&i.generator).len())
+        .max()
+        .unwrap_or(0);
+    for info in &infos {
+        println!(
+            "{name:<name_width$}  {ty:<type_width$}  {generator:<generator_width$}  {compiler}",
+            name = info.name,
+            ty = dash(&info.build_type),
+            generator = dash(&info.generator),
+            compiler = dash(&info.compiler),
+        );
+    }
+    Ok(())
+}
+
+// ========== Env command ==========
+
+pub(crate) async fn exec_env(
+    build: Option<String>,
+    run: Option<String>,
+    export: bool,
+) -> Result<()> {
+    let project = CMakeProject::new().await?;
+    let build_dir = if build.is_none() && project.build_dirs.is_empty() {
+        None
+    } else {
+        Some(project.resolve_build_dir(build.as_deref()).await?)
+    };
+    let env = match &run {
+        Some(target) => project
+            .env_config
+            .run_env(Some(target), build_dir.map(PathBuf::as_path)),
+        None => project
+            .env_config
+            .build_env(build_dir.map(PathBuf::as_path)),
+    };
+    let mut vars: Vec<(String, String)> = env.into_iter().collect();
+    vars.sort();
+    let prefix = if export { "export " } else { "" };
+    for (key, value) in vars {
+        println!("{prefix}{key}={}", shell_quote(&value));
+    }
+    Ok(())
+}
+
+/// `arg` as a single POSIX shell word, single-quoted unless it is plain.
+pub(crate) fn shell_quote(arg: &str) -> String {
+    let plain = !arg.is_empty()
+        && arg
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_./=:,+@%".contains(c));
+    if plain {
+        arg.to_string()
+    } else {
+        format!("'{}'", arg.replace('\'', r"'\''"))
+    }
+}
+
 // ========== BuildTU command ==========
 
-pub(crate) async fn exec_build_tu(name: Option<String>, build: Option<String>) -> Result<()> {
+pub(crate) async fn exec_build_tu(
+    name: Option<String>,
+    build: Option<String>,
+    print_command: bool,
+    jobs: Option<usize>,
+) -> Result<()> {
     let project = CMakeProject::new().await?;
+    // Resolved once so listing, printing and building all use the same dir.
+    let build = project.resolve_build_dir_name(build.as_deref()).await?;
     let tu = if let Some(name) = name {
         name
     } else {
-        let tu = project.list_all_translation_units(build.as_deref()).await?;
-        let tu = completing_read(&tu).await?;
+        let mut grouped = project
+            .list_translation_units_by_target(Some(&build))
+            .await?;
+        if grouped.is_empty() {
+            return Err(anyhow!("No translation units found"));
+        }
+        let mut target_names: Vec<String> = grouped.keys().cloned().collect();
+        target_names.sort();
+        let target_name = if target_names.len() == 1 {
+            target_names.remove(0)
+        } else {
+            let previews: HashMap<String, String> = grouped
+                .iter()
+                .map(|(target, tus)| (target.clone(), tus.join("\n")))
+                .collect();
+            let target_name = completing_read(&target_names, Some(&previews)).await?;
+            if target_name.is_empty() {
+                return Err(anyhow!("No target selected"));
+            }
+            target_name
+        };
+        let tus = grouped
+            .remove(&target_name)
+            .with_context(|| format!("Target {target_name} not found"))?;
+        let previews: HashMap<String, String> = tus
+            .iter()
+            .map(|tu| (tu.clone(), format!("source: {}", tu_source(tu))))
+            .collect();
+        let tu = completing_read(&tus, Some(&previews)).await?;
         if tu.is_empty() {
             return Err(anyhow!("No translation unit selected"));
         }
         tu
     };
-    println!("build TU: {tu}");
-    project.build_tu(&tu, None).await?;
+    println!("build TU: {}", style::cyan(&tu));
+    if print_command {
+        match project.find_compile_command(&tu, Some(&build)).await? {
+            Some(entry) => eprintln!("{}", entry.command_line()),
+            None => eprintln!("No compile command found for {tu}"),
+        }
+    }
+    project
+        .build_tu(&tu, Some(&build), resolve_job_count(jobs))
+        .await?;
+    Ok(())
+}
+
+/// Best-effort source path of a TU object such as
+/// `CMakeFiles/app.dir/src/main.cc.o`, relative to the target's source dir.
+fn tu_source(tu: &str) -> &str {
+    let rest = tu.split_once(".dir/").map_or(tu, |(_, rest)| rest);
+    rest.strip_suffix(".o")
+        .or_else(|| rest.strip_suffix(".obj"))
+        .unwrap_or(rest)
+}
+
+/// fzf preview for a target: its type, folder and first artifact.
+fn target_preview(target: &Target) -> String {
+    let artifact = target
+        .artifacts
+        .as_ref()
+        .and_then(|a| a.first())
+        .map_or("(none)", |a| a.path.as_str());
+    format!(
+        "type: {}\nfolder: {}\nartifact: {artifact}",
+        target.target_type,
+        target.folder_name().unwrap_or("-")
+    )
+}
+
+// ========== Configure command ==========
+
+/// Options of `cmk configure` (alias `refresh`).
+pub(crate) struct ConfigureOptions {
+    pub build: Option<String>,
+    pub toolchain: Option<PathBuf>,
+    pub definitions: Vec<String>,
+    pub sanitize: Option<String>,
+    pub generator: Option<String>,
+    pub build_type: Option<String>,
+    pub fresh: bool,
+}
+
+pub(crate) async fn exec_configure(options: ConfigureOptions) -> Result<()> {
+    let project = CMakeProject::new().await?;
+    resolve_sanitizers(&project, options.sanitize)?;
+    let toolchain = resolve_toolchain_file(&project, options.toolchain)?;
+    let mut definitions = resolve_definitions(&project, options.definitions)?;
+    // Per build dir, so not remembered in .cmk.toml like -D.
+    if let Some(build_type) = options.build_type {
+        definitions.insert("CMAKE_BUILD_TYPE".to_string(), build_type);
+    }
+    let start = Instant::now();
+    let result = project
+        .configure_with(
+            options.build.as_deref(),
+            toolchain.as_deref(),
+            &definitions,
+            options.generator.as_deref(),
+            true,
+            options.fresh,
+        )
+        .await?;
+    if let Some(result) = result {
+        warn_if_configured_linker_missing(&project)?;
+        if result.changed {
+            println!(
+                "Configured {} in {:.1}s",
+                result.build_dir.display(),
+                start.elapsed().as_secs_f64()
+            );
+        } else {
+            println!("Already up to date: {}", result.build_dir.display());
+        }
+    }
+    Ok(())
+}
+
+/// The toolchain file to configure with: `--toolchain` if given (remembered
+/// in `[cmake] toolchain_file` for later runs), else the remembered one.
+fn resolve_toolchain_file(
+    project: &CMakeProject,
+    toolchain: Option<PathBuf>,
+) -> Result<Option<PathBuf>> {
+    let config = CMakeConfig::load(&project.project_root)?;
+    let Some(toolchain) = toolchain else {
+        return Ok(config.toolchain_file(&project.project_root));
+    };
+    let toolchain = std::env::current_dir()?.join(toolchain);
+    if !toolchain.is_file() {
+        return Err(anyhow!("Toolchain file {} not found", toolchain.display()));
+    }
+    let stored = toolchain
+        .strip_prefix(&project.project_root)
+        .unwrap_or(&toolchain)
+        .to_string_lossy()
+        .to_string();
+    if config.toolchain_file.as_deref() != Some(stored.as_str()) {
+        CmkConfig::set_string(&project.project_root, "cmake", "toolchain_file", &stored)?;
+    }
+    Ok(Some(toolchain))
+}
+
+/// The `-D` definitions to configure with: those remembered in
+/// `[cmake.definitions]`, overridden by `KEY=VALUE` pairs from the command
+/// line, which are remembered in turn.
+fn resolve_definitions(
+    project: &CMakeProject,
+    definitions: Vec<String>,
+) -> Result<BTreeMap<String, String>> {
+    let config = CMakeConfig::load(&project.project_root)?;
+    let mut merged = config.definitions.clone();
+    for definition in definitions {
+        let (key, value) = definition
+            .split_once('=')
+            .filter(|(key, _)| !key.is_empty())
+            .with_context(|| format!("Invalid definition '{definition}', expected KEY=VALUE"))?;
+        if merged.get(key).map(String::as_str) != Some(value) {
+            CmkConfig::set_string(&project.project_root, "cmake.definitions", key, value)?;
+            merged.insert(key.to_string(), value.to_string());
+        }
+    }
+    config.apply_sanitizers(&mut merged);
+    config.apply_linker(&mut merged);
+    Ok(merged)
+}
+
+/// Warn if the `[cmake] linker` a configure just passed isn't installed.
+fn warn_if_configured_linker_missing(project: &CMakeProject) -> Result<()> {
+    let config = CMakeConfig::load(&project.project_root)?;
+    if let Some(linker) = config.linker.as_deref().filter(|l| !l.is_empty()) {
+        super::doctor::warn_if_linker_missing(linker);
+    }
+    Ok(())
+}
+
+/// Sanitizers `--sanitize` accepts, besides `none`.
+const SANITIZERS: &[&str] = &[
+    "address",
+    "undefined",
+    "thread",
+    "memory",
+    "leak",
+    "hwaddress",
+];
+
+/// Sanitizer pairs whose runtimes can't be combined in one binary.
+const INCOMPATIBLE_SANITIZERS: &[(&str, &str)] = &[
+    ("address", "thread"),
+    ("address", "memory"),
+    ("address", "hwaddress"),
+    ("thread", "memory"),
+    ("thread", "leak"),
+    ("thread", "hwaddress"),
+    ("memory", "leak"),
+    ("memory", "hwaddress"),
+];
+
+/// Fail if `names` (`-fsanitize=` names) include a pair of
+/// [`INCOMPATIBLE_SANITIZERS`].
+fn check_sanitizer_combination(names: &[&str]) -> Result<()> {
+    for (a, b) in INCOMPATIBLE_SANITIZERS {
+        if names.contains(a) && names.contains(b) {
+            return Err(anyhow!("Sanitizers {a} and {b} can't be used together"));
+        }
+    }
+    Ok(())
+}
+
+/// Check a `--sanitize` list and remember it in `[cmake] sanitize`, where
+/// [`resolve_definitions`] picks it up. `none` removes it.
+fn resolve_sanitizers(project: &CMakeProject, sanitize: Option<String>) -> Result<()> {
+    let Some(sanitize) = sanitize else {
+        return Ok(());
+    };
+    let list = if sanitize == "none" {
+        String::new()
+    } else {
+        let names: Vec<&str> = sanitize
+            .split(',')
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .collect();
+        if let Some(unknown) = names.iter().find(|name| !SANITIZERS.contains(name)) {
+            return Err(anyhow!(
+                "Unknown sanitizer '{unknown}', expected some of {} or none",
+                SANITIZERS.join(", ")
+            ));
+        }
+        check_sanitizer_combination(&names)?;
+        names.join(",")
+    };
+    let config = CMakeConfig::load(&project.project_root)?;
+    if config.sanitize.as_deref().unwrap_or_default() != list {
+        eprintln!(
+            "warning: changing sanitizers reconfigures the build dir and rebuilds every target"
+        );
+        if list.is_empty() {
+            CmkConfig::remove_key(&project.project_root, "cmake", "sanitize")?;
+        } else {
+            CmkConfig::set_string(&project.project_root, "cmake", "sanitize", &list)?;
+        }
+    }
     Ok(())
 }
 
-// ========== Refresh command ==========
+// ========== Compdb command ==========
 
-pub(crate) async fn exec_refresh(build: Option<String>) -> Result<()> {
+pub(crate) async fn exec_compdb(build: Option<String>, link: bool, merge: bool) -> Result<()> {
     let project = CMakeProject::new().await?;
-    project.refresh_build_dir(build.as_deref()).await?;
+    let root_cdb = project.project_root.join("compile_commands.json");
+
+    if merge {
+        let mut keys = project.list_build_dirs();
+        keys.sort();
+        let mut seen = std::collections::HashSet::new();
+        let mut merged = Vec::new();
+        for key in &keys {
+            let cdb = project.ensure_compile_commands(Some(key)).await?;
+            let content = std::fs::read_to_string(&cdb)
+                .with_context(|| format!("Failed to read {}", cdb.display()))?;
+            let entries: Vec<serde_json::Value> = serde_json::from_str(&content)
+                .with_context(|| format!("Failed to parse {}", cdb.display()))?;
+            // First build dir (in sorted order) wins for a file compiled by several.
+            for entry in entries {
+                let file = entry
+                    .get("file")
+                    .and_then(|f| f.as_str())
+                    .map(str::to_string);
+                if file.is_none_or(|f| seen.insert(f)) {
+                    merged.push(entry);
+                }
+            }
+        }
+        remove_if_exists(&root_cdb)?;
+        std::fs::write(&root_cdb, serde_json::to_string_pretty(&merged)?)?;
+        println!(
+            "Merged {} entries from {} build dir(s) into {}",
+            merged.len(),
+            keys.len(),
+            root_cdb.display()
+        );
+        return Ok(());
+    }
+
+    if link {
+        project.link_compile_commands(build.as_deref()).await?;
+    } else {
+        let cdb = project.ensure_compile_commands(build.as_deref()).await?;
+        println!("{}", cdb.display());
+    }
+    Ok(())
+}
+
+fn remove_if_exists(path: &Path) -> Result<()> {
+    if path.symlink_metadata().is_ok() {
+        std::fs::remove_file(path)
+            .with_context(|| format!("Failed to remove {}", path.display()))?;
+    }
     Ok(())
 }
 
@@ -450,27 +1653,61 @@ const CMK_TOML_TEMPLATE: &str = r#"# cmk project configuration. See https://gith
 # [build]
 # # Used when multiple build dirs exist and PWD isn't inside one.
 # default = "build/debug"
+# # Turn on CMAKE_EXPORT_COMPILE_COMMANDS when configuring (default: true).
+# export_compile_commands = true
+# # Symlink compile_commands.json into the project root after `cmk build`.
+# lsp_symlink = false
+# # Reconfigure instead of warning when a CMakeLists.txt is newer than the cache.
+# auto_reconfigure = false
+
+# [cmake]
+# # CMAKE_TOOLCHAIN_FILE for configure; `--toolchain` records it here.
+# toolchain_file = "cmake/aarch64-linux.cmake"
 
-# [vars]
-# DEPS_DIR = "${PROJECT_ROOT}/.deps"
-# DEPS_INSTALL = "${DEPS_DIR}/install"
+# # `-fsanitize=` list added to compile/link flags; `--sanitize` records it here.
+# sanitize = "address,undefined"
 
-# [env]
-# PATH = { prepend = ["${DEPS_INSTALL}/bin"] }
+# # Linker passed as `-fuse-ld=` in the link flags (mold, lld, gold).
+# linker = "mold"
+
+# # CPM_SOURCE_CACHE exported to cmake (default: ~/.cache/CPM; "" leaves it unset).
+# cpm_source_cache = "/var/cache/cpm"
+
+# [cmake.definitions]
+# # `-D` cache variables passed on configure; `-D KEY=VALUE` records them here.
+# BUILD_TESTING = "ON"
 
 [fmt]
 ignore = ["third_party/**", "build/**"]
+# extensions = ["cc", "h"]
 
 [lint]
 ignore = ["third_party/**", "build/**"]
 # warnings_as_errors = false
 # header_filter = "^(src|include)/"
 # extra_args = ["-quiet"]
+
+# # Shell commands run after a successful build, in order, from the project root.
+# [[hooks.post_build]]
+# command = 'cp -r assets "${PROJECT_BUILD_ROOT}/assets"'
+# # Only after building this target (or `all`); fail the build unless allow_failure.
+# target = "app"
+# allow_failure = false
+
+# # Run around `cmk run` with the run environment; post_run sees CMK_RUN_EXIT_CODE.
+# [[hooks.pre_run]]
+# command = 'mkdir -p "${PROJECT_BUILD_ROOT}/fixtures"'
+# [[hooks.post_run]]
+# command = 'rm -rf "${PROJECT_BUILD_ROOT}/fixtures"'
+
+# [bench]
+# # Glob patterns for `cmk bench` target names (default: bench_*, *_bench, *_benchmark).
+# patterns = ["bench_*", "*_bench", "*_benchmark"]
 "#;
 
 pub(crate) async fn exec_init(force: bool) -> Result<()> {
     let project_root = get_project_root().await?;
-    let path = project_root.join(".cmk.toml");
+    let path = CmkConfig::path(&project_root)?;
     if path.exists() && !force {
         return Err(anyhow!(
             "{} already exists (pass --force to overwrite)",
@@ -573,10 +1810,21 @@ fn resolve_single_file(name: &str) -> Result<PathBuf> {
     Ok(abs)
 }
 
+/// Matches `extensions` when non-empty, otherwise any C/C++ source.
+fn has_source_extension(path: &Path, extensions: &[String]) -> bool {
+    if extensions.is_empty() {
+        return is_c_or_cpp(path);
+    }
+    path.extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| extensions.iter().any(|x| x.trim_start_matches('.') == e))
+}
+
 async fn collect_source_files(
     project_root: &Path,
     selection: FileSelection,
     ignore_patterns: &[String],
+    extensions: &[String],
     verbose: bool,
 ) -> Result<Vec<PathBuf>> {
     let output_str = match selection {
@@ -634,7 +1882,7 @@ async fn collect_source_files(
     Ok(candidates
         .into_iter()
         .filter(|path| {
-            let is_src = is_c_or_cpp(path);
+            let is_src = has_source_extension(path, extensions);
             if verbose && !is_src {
                 println!("Skipping (not C/C++): {}", path.display());
             }
@@ -659,7 +1907,14 @@ pub(crate) async fn exec_fmt(
         vec![resolve_single_file(&name)?]
     } else {
         let selection = FileSelection::from_flags(all, staged, unstaged);
-        collect_source_files(&project_root, selection, &fmt_config.ignore, verbose).await?
+        collect_source_files(
+            &project_root,
+            selection,
+            &fmt_config.ignore,
+            &fmt_config.extensions,
+            verbose,
+        )
+        .await?
     };
 
     if files.is_empty() {
@@ -901,6 +2156,7 @@ fn compute_signature(
 pub(crate) async fn exec_lint(
     build: Option<String>,
     file: Option<String>,
+    target: Option<String>,
     interactive: bool,
     all: bool,
     staged: bool,
@@ -913,14 +2169,14 @@ pub(crate) async fn exec_lint(
     let project = CMakeProject::new().await?;
     let project_root = project.project_root.clone();
     let build_dir = project.resolve_build_dir(build.as_deref()).await?.clone();
+    let key = project
+        .build_dirs
+        .iter()
+        .find(|(_, p)| *p == &build_dir)
+        .map(|(k, _)| k.clone());
 
     let cdb = build_dir.join("compile_commands.json");
     if !cdb.exists() {
-        let key = project
-            .build_dirs
-            .iter()
-            .find(|(_, p)| *p == &build_dir)
-            .map(|(k, _)| k.clone());
         eprintln!(
             "compile_commands.json missing in {} — running `cmake` to generate it.",
             build_dir.display()
@@ -938,6 +2194,18 @@ pub(crate) async fn exec_lint(
 
     let files: Vec<PathBuf> = if let Some(name) = file {
         vec![resolve_single_file(&name)?]
+    } else if let Some(name) = target {
+        let target = project
+            .collect_all_targets(key.as_deref())
+            .await?
+            .into_iter()
+            .find(|t| t.name == name)
+            .with_context(|| format!("Target {name} not found"))?;
+        target
+            .source_paths(&project_root)
+            .into_iter()
+            .filter(|path| is_translation_unit(path))
+            .collect()
     } else if interactive {
         let candidates = read_compile_db_files(&cdb)?;
         if candidates.is_empty() {
@@ -952,7 +2220,7 @@ pub(crate) async fn exec_lint(
                     .to_string()
             })
             .collect();
-        let picked = completing_read(&display).await?;
+        let picked = completing_read(&display, None).await?;
         if picked.is_empty() {
             return Err(anyhow!("No source file selected"));
         }
@@ -970,7 +2238,8 @@ pub(crate) async fn exec_lint(
     } else {
         let selection = FileSelection::from_flags(all, staged, unstaged);
         let collected =
-            collect_source_files(&project_root, selection, &lint_config.ignore, verbose).await?;
+            collect_source_files(&project_root, selection, &lint_config.ignore, &[], verbose)
+                .await?;
         collected
             .into_iter()
             .filter(|path| {
@@ -1188,8 +2457,12 @@ pub(crate) async fn exec_lint(
         }
     }
 
+    let total_warnings: usize = reports.iter().map(|r| r.warnings).sum();
+    let total_errors: usize = reports.iter().map(|r| r.errors).sum();
     if failed > 0 {
-        return Err(anyhow!("{failed}/{total} file(s) failed clang-tidy"));
+        return Err(anyhow!(
+            "{failed}/{total} file(s) failed clang-tidy ({total_warnings} warning(s), {total_errors} error(s))"
+        ));
     }
     let hits = cache_hits.load(std::sync::atomic::Ordering::Relaxed);
     let cache_note = if cache_enabled {
@@ -1198,7 +2471,7 @@ pub(crate) async fn exec_lint(
         String::new()
     };
     println!(
-        "Linted {total} file(s){cache_note}, {} with diagnostics.",
+        "Linted {total} file(s){cache_note}, {} with diagnostics ({total_warnings} warning(s), {total_errors} error(s)).",
         reports.len()
     );
     Ok(())
@@ -1207,3 +2480,46 @@ pub(crate) async fn exec_lint(
 fn count_diag(text: &str, marker: &str) -> usize {
     text.lines().filter(|l| l.contains(marker)).count()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(|n| n.to_string()).collect()
+    }
+
+    #[test]
+    fn incompatible_sanitizers_are_rejected() {
+        assert!(check_sanitizer_combination(&[]).is_ok());
+        assert!(check_sanitizer_combination(&["address", "undefined", "leak"]).is_ok());
+        assert!(check_sanitizer_combination(&["thread", "undefined"]).is_ok());
+        for names in [
+            ["address", "thread"],
+            ["memory", "address"],
+            ["thread", "memory"],
+            ["leak", "thread"],
+        ] {
+            assert!(check_sanitizer_combination(&names).is_err(), "{names:?}");
+        }
+    }
+
+    #[test]
+    fn fuzzy_matches_prefers_substrings() {
+        let targets = names(&["my_server", "server_test", "client", "srv_tool"]);
+        assert_eq!(
+            fuzzy_matches("server", &targets),
+            names(&["my_server", "server_test"])
+        );
+        assert_eq!(fuzzy_matches("SRV", &targets), names(&["srv_tool"]));
+    }
+
+    #[test]
+    fn fuzzy_matches_falls_back_to_subsequences() {
+        let targets = names(&["my_server", "client"]);
+        assert_eq!(fuzzy_matches("msv", &targets), names(&["my_server"]));
+        assert_eq!(fuzzy_matches("cnt", &targets), names(&["client"]));
+        assert!(fuzzy_matches("xyz", &targets).is_empty());
+        assert!(fuzzy_matches("tneilc", &targets).is_empty());
+    }
+}
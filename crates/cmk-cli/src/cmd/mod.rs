@@ -1,4 +1,8 @@
+pub mod doctor;
+pub mod history;
 pub mod legacy;
+pub mod presets;
+pub mod status;
 pub mod toolchain;
 
 pub(crate) use legacy::*;
@@ -0,0 +1,188 @@
+//! `cmk presets export`: write a `CMakeUserPresets.json` with a configure
+//! preset per build dir, so IDEs that only read presets configure with the
+//! same settings and environment as cmk.
+
+use std::{collections::BTreeMap, path::Path};
+
+use anyhow::{Context, Result, anyhow};
+use cmk_config::CMakeConfig;
+use cmk_project::{BuildDirInfo, CMakeProject};
+use serde_json::{Value, json};
+
+const PRESETS_FILE: &str = "CMakeUserPresets.json";
+
+/// `vendor` key marking a presets file as written by cmk, so it may be
+/// regenerated without `--force`.
+const VENDOR_KEY: &str = "cmk";
+
+/// Whether the presets file `content` was written by this command.
+fn is_generated(content: &str) -> bool {
+    serde_json::from_str::<Value>(content)
+        .is_ok_and(|presets| presets.pointer(&format!("/vendor/{VENDOR_KEY}")).is_some())
+}
+
+/// Preset name for a build dir: its path relative to the project root, with
+/// separators turned into `-` (`build/debug` → `build-debug`).
+fn preset_name(build_dir: &str) -> String {
+    build_dir.replace(['/', '\\'], "-")
+}
+
+/// The configure preset for one build dir. `definitions` keys may carry a
+/// type (`NAME:TYPE`), which presets spell as `{"type", "value"}`.
+fn configure_preset(
+    info: &BuildDirInfo,
+    definitions: &BTreeMap<String, String>,
+    toolchain_file: Option<&Path>,
+    environment: &BTreeMap<String, String>,
+) -> Value {
+    let mut cache_variables: BTreeMap<String, Value> = definitions
+        .iter()
+        .map(|(key, value)| match key.split_once(':') {
+            Some((name, kind)) => (name.to_string(), json!({ "type": kind, "value": value })),
+            None => (key.clone(), json!(value)),
+        })
+        .collect();
+    if let Some(build_type) = &info.build_type {
+        cache_variables.insert("CMAKE_BUILD_TYPE".to_string(), json!(build_type));
+    }
+    let mut preset = json!({
+        "name": preset_name(&info.name),
+        "displayName": format!("cmk: {}", info.name),
+        "binaryDir": format!("${{sourceDir}}/{}", info.name),
+        "cacheVariables": cache_variables,
+        "environment": environment,
+    });
+    if let Some(generator) = &info.generator {
+        preset["generator"] = json!(generator);
+    }
+    if let Some(toolchain_file) = toolchain_file {
+        preset["toolchainFile"] = json!(toolchain_file);
+    }
+    preset
+}
+
+pub async fn export(force: bool) -> Result<()> {
+    let project = CMakeProject::new().await?;
+    let path = project.project_root.join(PRESETS_FILE);
+    if path.exists() && !force {
+        let content = std::fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        if !is_generated(&content) {
+            return Err(anyhow!(
+                "{} was not written by cmk; pass --force to overwrite it",
+                path.display()
+            ));
+        }
+    }
+
+    let config = CMakeConfig::load(&project.project_root)?;
+    let mut definitions = config.definitions.clone();
+    config.apply_sanitizers(&mut definitions);
+    config.apply_linker(&mut definitions);
+    let toolchain_file = config.toolchain_file(&project.project_root);
+
+    let mut infos = project.build_dir_info();
+    infos.sort_by(|a, b| a.name.cmp(&b.name));
+    let mut configure_presets = Vec::new();
+    for info in &infos {
+        let environment = project
+            .env_config
+            .build_env(Some(&info.path))
+            .into_iter()
+            .collect();
+        configure_presets.push(configure_preset(
+            info,
+            &definitions,
+            toolchain_file.as_deref(),
+            &environment,
+        ));
+    }
+
+    let presets = json!({
+        "version": 3,
+        "vendor": { VENDOR_KEY: { "generated": true } },
+        "configurePresets": configure_presets,
+    });
+    std::fs::write(&path, serde_json::to_string_pretty(&presets)? + "\n")
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+    println!(
+        "Wrote {} configure preset(s) to {}",
+        infos.len(),
+        path.display()
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    #[test]
+    fn generated_files_are_recognized() {
+        assert!(is_generated(
+            r#"{"version": 3, "vendor": {"cmk": {"generated": true}}}"#
+        ));
+        assert!(!is_generated(r#"{"version": 3, "vendor": {"other": {}}}"#));
+        assert!(!is_generated(r#"{"version": 3}"#));
+        assert!(!is_generated("not json"));
+    }
+
+    #[test]
+    fn preset_names_flatten_paths() {
+        assert_eq!(preset_name("build"), "build");
+        assert_eq!(preset_name("build/debug"), "build-debug");
+        assert_eq!(preset_name(r"out\release"), "out-release");
+    }
+
+    #[test]
+    fn configure_preset_carries_cmk_settings() {
+        let info = BuildDirInfo {
+            name: "build/debug".to_string(),
+            path: PathBuf::from("/p/build/debug"),
+            generator: Some("Ninja".to_string()),
+            build_type: Some("Debug".to_string()),
+            compiler: None,
+        };
+        let definitions = BTreeMap::from([
+            ("FOO:BOOL".to_string(), "ON".to_string()),
+            ("BAR".to_string(), "1".to_string()),
+        ]);
+        let environment = BTreeMap::from([(
+            "CPM_SOURCE_CACHE".to_string(),
+            "/home/u/.cache/CPM".to_string(),
+        )]);
+        let preset = configure_preset(
+            &info,
+            &definitions,
+            Some(Path::new("/p/cmake/arm.cmake")),
+            &environment,
+        );
+        assert_eq!(
+            preset,
+            json!({
+                "name": "build-debug",
+                "displayName": "cmk: build/debug",
+                "binaryDir": "${sourceDir}/build/debug",
+                "generator": "Ninja",
+                "toolchainFile": "/p/cmake/arm.cmake",
+                "cacheVariables": {
+                    "FOO": { "type": "BOOL", "value": "ON" },
+                    "BAR": "1",
+                    "CMAKE_BUILD_TYPE": "Debug",
+                },
+                "environment": { "CPM_SOURCE_CACHE": "/home/u/.cache/CPM" },
+            })
+        );
+
+        let bare = BuildDirInfo {
+            generator: None,
+            build_type: None,
+            ..info
+        };
+        let preset = configure_preset(&bare, &BTreeMap::new(), None, &BTreeMap::new());
+        assert!(preset.get("generator").is_none());
+        assert!(preset.get("toolchainFile").is_none());
+        assert_eq!(preset["cacheVariables"], json!({}));
+    }
+}
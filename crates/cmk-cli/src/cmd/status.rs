@@ -0,0 +1,92 @@
+//! `cmk status`: what cmk has discovered about the current project, for
+//! debugging setup issues.
+
+use std::path::PathBuf;
+
+use anyhow::Result;
+use cmk_config::{BuildEnv, CmkConfig};
+use cmk_project::{CMakeProject, get_project_root};
+
+/// External tools whose resolved paths are listed.
+const TOOLS: &[&str] = &["cmake", "ninja", "git", "fzf", "clang-format", "clang-tidy"];
+
+/// First `name` executable on `PATH`.
+pub(crate) fn find_in_path(name: &str) -> Option<PathBuf> {
+    std::env::split_paths(&std::env::var_os("PATH")?)
+        .map(|dir| dir.join(name))
+        .find(|path| path.is_file())
+}
+
+pub async fn run() -> Result<()> {
+    let root = get_project_root().await?;
+    println!("project root: {}", root.display());
+
+    let config_path = CmkConfig::path(&root)?;
+    if config_path.is_file() {
+        println!("config: {}", config_path.display());
+    } else {
+        println!("config: none ({} does not exist)", config_path.display());
+    }
+    let config = CmkConfig::load(&root)?;
+    let toolchain_file = config.cmake.toolchain_file(&root);
+    println!(
+        "toolchain file: {}",
+        toolchain_file.map_or("-".to_string(), |p| p.display().to_string())
+    );
+    if let Some(sanitize) = config.cmake.sanitize.filter(|s| !s.is_empty()) {
+        println!("sanitizers: {sanitize}");
+    }
+    if let Some(linker) = config.cmake.linker.filter(|l| !l.is_empty()) {
+        println!("linker: {linker}");
+    }
+    if let Some(toolchain) = config.toolchain.and_then(|t| t.use_) {
+        println!("toolchain: {toolchain}");
+    }
+    let cpm_source_cache = match BuildEnv::load(&root)?.cpm_source_cache(None) {
+        Ok(cache) => cache.map_or("-".to_string(), |p| p.display().to_string()),
+        // Uses ${PROJECT_BUILD_ROOT}, so it differs per build dir.
+        Err(_) => format!(
+            "{} (per build dir)",
+            config.cmake.cpm_source_cache.as_deref().unwrap_or_default()
+        ),
+    };
+    println!("CPM source cache: {cpm_source_cache}");
+
+    match CMakeProject::new().await {
+        Ok(project) => {
+            let current = project.detect_pwd_key();
+            println!("build dirs:");
+            for info in project.build_dir_info() {
+                let executables = project
+                    .collect_executable_targets(Some(&info.name))
+                    .await
+                    .map_or("-".to_string(), |targets| targets.len().to_string());
+                let dash = |v: &Option<String>| v.clone().unwrap_or_else(|| "-".to_string());
+                let marker = if current.as_ref() == Some(&info.name) {
+                    "*"
+                } else {
+                    " "
+                };
+                println!(
+                    "{marker} {}  {}  {}  {}  executables: {executables}",
+                    info.name,
+                    dash(&info.build_type),
+                    dash(&info.generator),
+                    dash(&info.compiler),
+                );
+            }
+            if let Some(default) = &project.build_config.default {
+                println!("default build dir: {default}");
+            }
+        }
+        Err(e) => println!("build dirs: none ({e})"),
+    }
+
+    println!("tools:");
+    let width = TOOLS.iter().map(|t| t.len()).max().unwrap_or(0);
+    for tool in TOOLS {
+        let path = find_in_path(tool).map_or("not found".to_string(), |p| p.display().to_string());
+        println!("  {tool:<width$}  {path}");
+    }
+    Ok(())
+}
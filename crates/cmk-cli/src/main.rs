@@ -1,8 +1,13 @@
-use std::path::PathBuf;
+use std::{path::PathBuf, time::Duration};
 
 use anyhow::Result;
 use clap::{CommandFactory, Parser};
 use clap_complete::Shell;
+use cmk_core::{
+    UserAbort,
+    style::{self, ColorChoice},
+};
+use cmk_project::CmkError;
 
 mod cmd;
 
@@ -11,7 +16,8 @@ mod cmd;
 struct Cli {
     #[clap(subcommand)]
     command: Option<SubCommand>,
-    /// Build dir override (for the implicit `cmk` → `cmk build` shortcut)
+    /// Build dir override (for the implicit `cmk` → `cmk build` shortcut;
+    /// default: CMK_BUILD_DIR)
     #[clap(short, long, value_name = "BUILD_DIR")]
     build: Option<String>,
     /// Pick the build target interactively via fzf
@@ -20,9 +26,19 @@ struct Cli {
     /// Number of parallel build jobs
     #[clap(short, long)]
     jobs: Option<usize>,
-    /// Specific target name to build
+    /// Specific target name to build. Repeatable
     #[clap(short, long)]
-    target: Option<String>,
+    target: Vec<String>,
+    /// Report the result of `build`/`run` as a JSON object on stdout
+    #[clap(long)]
+    json: bool,
+    /// When to color output. `auto` colors terminals unless NO_COLOR is set
+    #[clap(long, global = true, value_enum, default_value_t = ColorArg::Auto)]
+    color: ColorArg,
+    /// Use this directory as the project root instead of the enclosing git
+    /// repository (also CMK_ROOT)
+    #[clap(long, global = true, value_name = "PATH")]
+    root: Option<PathBuf>,
 }
 
 #[derive(Debug, clap::Subcommand)]
@@ -36,6 +52,22 @@ enum SubCommand {
         /// CMakeLists.txt. Comments and formatting are preserved.
         #[clap(short, long)]
         project: bool,
+        /// Like --project, but into the nearest CMakeLists.txt above the
+        /// current directory
+        #[clap(short = 'I', long, conflicts_with = "project")]
+        inject: bool,
+        /// The alias to track the package under (default: the repo name)
+        #[clap(long = "as", value_name = "ALIAS")]
+        alias: Option<String>,
+        /// Track pre-releases as well (also on every `cmk update`)
+        #[clap(long)]
+        pre_release: bool,
+        /// Replace an alias that already points at a different package
+        #[clap(short, long)]
+        force: bool,
+        /// Download every release asset to record its SHA256
+        #[clap(long)]
+        checksum: bool,
     },
     /// Update the package index
     #[clap(name = "update", visible_alias = "u")]
@@ -47,12 +79,44 @@ enum SubCommand {
         /// Skip the confirmation prompt before applying project edits
         #[clap(short, long)]
         yes: bool,
+        /// Fail when any package can't be queried, instead of skipping it
+        #[clap(long)]
+        fail_fast: bool,
+    },
+    /// Restore the release a package had before the last update
+    #[clap(name = "rollback")]
+    Rollback {
+        /// The name or alias of the package. Rolls back every package with a
+        /// recorded previous release when omitted.
+        name: Option<String>,
+    },
+    /// Record the package versions CPM resolved in a build dir's cache as
+    /// the package index releases
+    #[clap(name = "pin")]
+    Pin {
+        /// The path to the build directory relative to the project root (default:
+        /// CMK_BUILD_DIR)
+        #[clap(short, long)]
+        build: Option<String>,
+    },
+    /// Merge another pkg.json into the package index
+    #[clap(name = "import")]
+    Import {
+        /// Path to the pkg.json to import
+        path: PathBuf,
+        /// Which side wins on conflicting entries. Without it, conflicts are
+        /// an error.
+        #[clap(long, value_enum)]
+        prefer: Option<PreferArg>,
     },
     /// Get the cached release of a package in the package index
     #[clap(name = "get", visible_alias = "g")]
     Get {
         /// The name or alias of the package
         name: String,
+        /// Print only the download URL of this asset of the current release
+        #[clap(long, value_name = "NAME")]
+        asset: Option<String>,
     },
     /// Create a new project
     #[clap(name = "new", visible_alias = "n")]
@@ -62,26 +126,133 @@ enum SubCommand {
         /// The name of the template to use
         #[clap(short, long)]
         template: Option<String>,
+        /// Skip `git init` and don't write a `.gitignore`
+        #[clap(long)]
+        no_git: bool,
+        /// The C++ standard to set in the generated CMakeLists.txt
+        #[clap(short, long, default_value = "23", value_parser = ["17", "20", "23", "26"])]
+        std: String,
+        /// Don't query GitHub: use the cached CPM version and the package
+        /// index (or built-in fallback) versions
+        #[clap(long)]
+        offline: bool,
+        /// Create cmake/ with a starter FindExample.cmake module and add it
+        /// to CMAKE_MODULE_PATH
+        #[clap(short = 'm', long)]
+        cmake_modules: bool,
+        /// Don't add a CPM package-lock.cmake or the CPMUsePackageLock call
+        #[clap(long)]
+        no_lock: bool,
+        /// Link with this linker (`add_link_options(-fuse-ld=...)`)
+        #[clap(long, value_enum)]
+        linker: Option<LinkerArg>,
+        /// Leave the Debug-only sanitizer options out of the template
+        #[clap(long)]
+        no_sanitizers: bool,
+        /// Sanitizers the template enables for Debug builds (default:
+        /// asan,ubsan)
+        #[clap(
+            long,
+            value_enum,
+            value_delimiter = ',',
+            conflicts_with = "no_sanitizers"
+        )]
+        sanitizers: Vec<SanitizerArg>,
     },
     /// Run the executable target
     #[clap(name = "run", visible_alias = "r")]
     Run {
-        /// The path to the build directory relative to the project root
+        /// The path to the build directory relative to the project root (default:
+        /// CMK_BUILD_DIR)
         #[clap(short, long)]
         build: Option<String>,
+        /// Use the build dir configured with CMAKE_BUILD_TYPE=Release
+        #[clap(long, conflicts_with_all = ["build", "debug"])]
+        release: bool,
+        /// Use the build dir configured with CMAKE_BUILD_TYPE=Debug
+        #[clap(long, conflicts_with = "build")]
+        debug: bool,
         /// The name of the executable target
         #[clap(short, long)]
         target: Option<String>,
+        /// Only offer targets whose CMake FOLDER is this one or below it
+        #[clap(long)]
+        folder: Option<String>,
+        /// Pick the arguments from the ones previously used with the target
+        #[clap(long, conflicts_with = "args")]
+        history: bool,
+        /// Stop the target if it runs longer than this (e.g. 500ms, 10s, 2m)
+        #[clap(long, value_parser = parse_duration)]
+        timeout: Option<Duration>,
+        /// Run the target with stdin closed instead of forwarding cmk's own
+        #[clap(long)]
+        no_stdin: bool,
+        /// Read arguments from this file, ahead of any given after `--`.
+        /// Blank lines and lines starting with `#` are skipped
+        #[clap(long, value_name = "PATH", conflicts_with = "history")]
+        args_file: Option<PathBuf>,
+        /// Whether each line or each word of --args-file is an argument
+        #[clap(long, value_enum, default_value_t, requires = "args_file")]
+        args_split: ArgsSplitArg,
         /// The arguments to pass to the executable target
         #[clap(last = true)]
         args: Vec<String>,
     },
+    /// Build and run a Google Benchmark target (by default one named
+    /// `bench_*`, `*_bench` or `*_benchmark`; see `[bench] patterns`)
+    #[clap(name = "bench")]
+    Bench {
+        /// The benchmark target (partial names match as in `run`)
+        #[clap(conflicts_with = "target")]
+        name: Option<String>,
+        /// The path to the build directory relative to the project root (default:
+        /// CMK_BUILD_DIR)
+        #[clap(short, long)]
+        build: Option<String>,
+        /// The benchmark target, like the positional name
+        #[clap(short, long)]
+        target: Option<String>,
+        /// Only run benchmarks matching this regex (--benchmark_filter)
+        #[clap(long, value_name = "REGEX")]
+        benchmark_filter: Option<String>,
+        /// Output format (--benchmark_format, and --benchmark_out_format
+        /// with --benchmark-out)
+        #[clap(long, value_enum)]
+        benchmark_format: Option<BenchFormatArg>,
+        /// Also write the results to this file (--benchmark_out)
+        #[clap(long, value_name = "PATH")]
+        benchmark_out: Option<PathBuf>,
+        /// More arguments for the benchmark executable
+        #[clap(last = true)]
+        args: Vec<String>,
+    },
+    /// Print the absolute path of an executable target's artifact
+    #[clap(name = "which")]
+    Which {
+        /// The path to the build directory relative to the project root (default:
+        /// CMK_BUILD_DIR)
+        #[clap(short, long)]
+        build: Option<String>,
+        /// Build the target first
+        #[clap(long)]
+        build_first: bool,
+        /// The name of the executable target (partial names match as in
+        /// `run`)
+        target: Option<String>,
+    },
     /// Build the project
     #[clap(name = "build", visible_alias = "b")]
     Build {
-        /// The path to the build directory relative to the project root
+        /// The path to the build directory relative to the project root (default:
+        /// CMK_BUILD_DIR)
         #[clap(short, long)]
         build: Option<String>,
+        /// Use the build dir configured with CMAKE_BUILD_TYPE=Release
+        #[clap(long, conflicts_with_all = ["build", "debug"])]
+        release: bool,
+        /// Use the build dir configured with CMAKE_BUILD_TYPE=Debug
+        #[clap(long, conflicts_with = "build")]
+        debug: bool,
         /// Select the target to build interactively. When the target is
         /// specified, this option is ignored.
         #[clap(short, long, default_value_t = false)]
@@ -89,23 +260,152 @@ enum SubCommand {
         /// Run n jobs in parallel
         #[clap(short, long)]
         jobs: Option<usize>,
-        /// The name of the executable target
+        /// Symlink compile_commands.json into the project root after building
+        #[clap(long)]
+        lsp_symlink: bool,
+        /// Configure with this CMAKE_TOOLCHAIN_FILE first if the build dir
+        /// uses a different one (remembered in .cmk.toml)
+        #[clap(short = 'T', long, value_name = "PATH")]
+        toolchain: Option<PathBuf>,
+        /// Set a CMake cache variable, reconfiguring if the cache differs
+        /// (remembered in .cmk.toml). Repeatable.
+        #[clap(short = 'D', value_name = "KEY=VALUE")]
+        define: Vec<String>,
+        /// Build with these sanitizers, e.g. address,undefined, or `none` to
+        /// stop (remembered in .cmk.toml)
+        #[clap(long, value_name = "LIST")]
+        sanitize: Option<String>,
+        /// Only offer targets whose CMake FOLDER is this one or below it
+        #[clap(long)]
+        folder: Option<String>,
+        /// Show a progress bar while Ninja builds, printing only compiler
+        /// output
+        #[clap(long)]
+        progress: bool,
+        /// Only run the CMake configure step for the build dir, like
+        /// `cmk configure`, and build nothing
+        #[clap(short = 'C', long, conflicts_with_all = ["targets", "extra_targets", "tool_args"])]
+        config_only: bool,
+        /// Build every target (of any type) whose whole name matches this
+        /// regex, e.g. '.*_test'
+        #[clap(long, value_name = "REGEX", conflicts_with_all = ["targets", "extra_targets", "config_only"])]
+        pattern: Option<String>,
+        /// Also build this target (repeatable). Built after the positional
+        /// targets, in one cmake invocation; duplicates are built once
+        #[clap(short = 't', long = "target", value_name = "TARGET")]
+        extra_targets: Vec<String>,
+        /// The names of the targets to build (default: all)
+        #[clap(value_name = "TARGET")]
+        targets: Vec<String>,
+        /// Flags passed verbatim to the build tool, e.g. `-- -d explain`
+        #[clap(last = true, value_name = "TOOL_ARGS")]
+        tool_args: Vec<String>,
+    },
+    /// List build directories with their generator, build type and compiler
+    #[clap(name = "builds")]
+    Builds,
+    /// List every target in the build dir with its type
+    #[clap(name = "targets")]
+    Targets {
+        /// The path to the build directory relative to the project root (default:
+        /// CMK_BUILD_DIR)
+        #[clap(short, long)]
+        build: Option<String>,
+        /// Only list targets of this type, e.g. EXECUTABLE or STATIC_LIBRARY
+        /// (case-insensitive; LIBRARY matches every library type)
+        #[clap(long = "type", value_name = "TYPE")]
+        target_type: Option<String>,
+        /// Print a JSON array of {name, type, artifacts} objects
+        #[clap(long)]
+        json: bool,
+    },
+    /// Print the target dependency graph in Graphviz DOT format, e.g.
+    /// `cmk graph | dot -Tsvg > graph.svg`
+    #[clap(name = "graph")]
+    Graph {
+        /// The path to the build directory relative to the project root (default:
+        /// CMK_BUILD_DIR)
+        #[clap(short, long)]
+        build: Option<String>,
+        /// Only show this target and what it transitively depends on
+        #[clap(short, long)]
         target: Option<String>,
     },
+    /// Print the environment cmk applies to build (or run) commands, one
+    /// KEY=VALUE per line
+    #[clap(name = "env")]
+    Env {
+        /// The path to the build directory relative to the project root (default:
+        /// CMK_BUILD_DIR)
+        #[clap(short, long)]
+        build: Option<String>,
+        /// Print the environment for running this target instead
+        #[clap(long, value_name = "TARGET")]
+        run: Option<String>,
+        /// Prefix each line with `export`, for `eval "$(cmk env --export)"`
+        #[clap(long)]
+        export: bool,
+    },
     /// Build the translation unit
     #[clap(name = "build-tu", visible_alias = "tu")]
     BuildTU {
-        /// The path to the build directory relative to the project root
+        /// The path to the build directory relative to the project root (default:
+        /// CMK_BUILD_DIR)
         #[clap(short, long)]
         build: Option<String>,
+        /// Print the TU's compile command from compile_commands.json to
+        /// stderr before building it
+        #[clap(short = 'P', long)]
+        print_command: bool,
+        /// Run n jobs in parallel
+        #[clap(short, long)]
+        jobs: Option<usize>,
         /// The name of the translation unit
         name: Option<String>,
     },
-    /// Refresh the CMake build directory
-    #[clap(name = "refresh", visible_alias = "ref")]
-    Refresh {
-        /// The path to the build directory relative to the project root
+    /// Run the CMake configure step for the build directory
+    #[clap(name = "configure", visible_aliases = ["refresh", "ref"])]
+    Configure {
+        /// The path to the build directory relative to the project root (default:
+        /// CMK_BUILD_DIR)
         build: Option<String>,
+        /// Configure with this CMAKE_TOOLCHAIN_FILE (remembered in .cmk.toml)
+        #[clap(short = 'T', long, value_name = "PATH")]
+        toolchain: Option<PathBuf>,
+        /// Set a CMake cache variable (remembered in .cmk.toml). Repeatable.
+        #[clap(short = 'D', value_name = "KEY=VALUE")]
+        define: Vec<String>,
+        /// Build with these sanitizers, e.g. address,undefined, or `none` to
+        /// stop (remembered in .cmk.toml)
+        #[clap(long, value_name = "LIST")]
+        sanitize: Option<String>,
+        /// Configure with this CMake generator, e.g. Ninja; a build dir
+        /// generated differently is reconfigured from scratch
+        #[clap(short = 'G', long, value_name = "NAME")]
+        generator: Option<String>,
+        /// Configure with this CMAKE_BUILD_TYPE, e.g. Release (not
+        /// remembered)
+        #[clap(long, value_name = "TYPE")]
+        config: Option<String>,
+        /// Discard the existing CMakeCache.txt and configure from scratch
+        #[clap(long)]
+        fresh: bool,
+    },
+    /// Ensure compile_commands.json is exported by the build directory
+    #[clap(name = "compdb")]
+    Compdb {
+        /// The path to the build directory relative to the project root (default:
+        /// CMK_BUILD_DIR)
+        #[clap(short, long, conflicts_with = "merge")]
+        build: Option<String>,
+        /// Symlink the build directory's compile_commands.json into the
+        /// project root
+        #[clap(short, long)]
+        link: bool,
+        /// Merge the databases of every build directory into
+        /// `<project_root>/compile_commands.json`
+        #[clap(short, long, conflicts_with = "link")]
+        merge: bool,
     },
     /// Format source files with clang-format
     #[clap(name = "fmt", visible_alias = "f")]
@@ -123,8 +423,9 @@ enum SubCommand {
         /// Format only unstaged files
         #[clap(short, long, conflicts_with_all = ["all", "staged"])]
         unstaged: bool,
-        /// Print files that would be formatted without modifying them
-        #[clap(short, long)]
+        /// Print files that would be formatted without modifying them and
+        /// exit non-zero if any would change (for CI)
+        #[clap(short, long, visible_alias = "check")]
         dry_run: bool,
         /// Print verbose output
         #[clap(short, long)]
@@ -143,6 +444,18 @@ enum SubCommand {
         #[clap(short, long)]
         force: bool,
     },
+    /// Manage package aliases in the package index
+    #[clap(name = "alias")]
+    Alias {
+        #[clap(subcommand)]
+        cmd: AliasCmd,
+    },
+    /// Share cmk's build dir settings with IDEs through CMake presets
+    #[clap(name = "presets")]
+    Presets {
+        #[clap(subcommand)]
+        cmd: PresetsCmd,
+    },
     /// Manage CPM dependencies in the project's CMakeLists.txt
     #[clap(name = "pkg")]
     Pkg {
@@ -150,15 +463,19 @@ enum SubCommand {
         cmd: PkgCmd,
     },
     /// Lint source files with clang-tidy
-    #[clap(name = "lint", visible_alias = "l")]
+    #[clap(name = "lint", visible_aliases = ["l", "tidy"])]
     Lint {
-        /// The path to the build directory relative to the project root
-        #[clap(short, long)]
+        /// The path to the build directory relative to the project root (default:
+        /// CMK_BUILD_DIR)
+        #[clap(short, long, visible_short_alias = 'p')]
         build: Option<String>,
         /// Lint a single source file (path relative to PWD or absolute).
         /// Skips git-based selection.
-        #[clap(conflicts_with_all = ["all", "staged", "unstaged", "interactive"])]
+        #[clap(conflicts_with_all = ["all", "staged", "unstaged", "interactive", "target"])]
         file: Option<String>,
+        /// Lint the sources of a single CMake target
+        #[clap(short, long, conflicts_with_all = ["all", "staged", "unstaged", "interactive"])]
+        target: Option<String>,
         /// Pick a single source file from compile_commands.json interactively
         #[clap(short, long, conflicts_with_all = ["all", "staged", "unstaged"])]
         interactive: bool,
@@ -184,6 +501,13 @@ enum SubCommand {
         #[clap(short, long)]
         verbose: bool,
     },
+    /// Check that the external tools cmk relies on are installed
+    #[clap(name = "doctor")]
+    Doctor,
+    /// Show the project root, config file, build dirs and tool paths cmk
+    /// has discovered
+    #[clap(name = "status", visible_alias = "info")]
+    Status,
     /// Manage clang/LLVM toolchains (install, switch, build, etc.)
     #[clap(name = "toolchain")]
     Toolchain {
@@ -220,6 +544,114 @@ enum PkgCmd {
     },
 }
 
+#[derive(Copy, Clone, Debug, clap::ValueEnum)]
+enum ColorArg {
+    Auto,
+    Always,
+    Never,
+}
+
+/// How `run --args-file` splits the file into arguments.
+#[derive(Copy, Clone, Debug, Default, clap::ValueEnum)]
+pub enum ArgsSplitArg {
+    /// One argument per line, spaces included
+    #[default]
+    Lines,
+    /// Every whitespace-separated word is an argument
+    Words,
+}
+
+/// Linkers `new --linker` can set up.
+#[derive(Copy, Clone, Debug, clap::ValueEnum)]
+pub enum LinkerArg {
+    Mold,
+    Lld,
+    Gold,
+}
+
+impl LinkerArg {
+    /// The name `-fuse-ld=` takes.
+    pub fn name(self) -> &'static str {
+        match self {
+            LinkerArg::Mold => "mold",
+            LinkerArg::Lld => "lld",
+            LinkerArg::Gold => "gold",
+        }
+    }
+}
+
+/// Sanitizers `new --sanitizers` can put in the template.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum SanitizerArg {
+    Asan,
+    Ubsan,
+    Tsan,
+    Msan,
+    Lsan,
+}
+
+impl SanitizerArg {
+    /// The name `-fsanitize=` takes.
+    pub fn name(self) -> &'static str {
+        match self {
+            SanitizerArg::Asan => "address",
+            SanitizerArg::Ubsan => "undefined",
+            SanitizerArg::Tsan => "thread",
+            SanitizerArg::Msan => "memory",
+            SanitizerArg::Lsan => "leak",
+        }
+    }
+}
+
+/// Google Benchmark output formats for `bench --benchmark-format`.
+#[derive(Copy, Clone, Debug, clap::ValueEnum)]
+pub enum BenchFormatArg {
+    Console,
+    Json,
+    Csv,
+}
+
+impl BenchFormatArg {
+    /// The name Google Benchmark's format flags take.
+    pub fn name(self) -> &'static str {
+        match self {
+            BenchFormatArg::Console => "console",
+            BenchFormatArg::Json => "json",
+            BenchFormatArg::Csv => "csv",
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, clap::ValueEnum)]
+pub enum PreferArg {
+    Theirs,
+    Mine,
+}
+
+#[derive(Debug, clap::Subcommand)]
+enum AliasCmd {
+    /// Rename an alias without re-querying GitHub
+    #[clap(name = "rename")]
+    Rename {
+        /// The existing alias
+        old: String,
+        /// The new alias; must not already exist
+        new: String,
+    },
+}
+
+#[derive(Debug, clap::Subcommand)]
+enum PresetsCmd {
+    /// Write CMakeUserPresets.json with a configure preset per build dir,
+    /// including the environment cmk configures with
+    #[clap(name = "export")]
+    Export {
+        /// Overwrite a CMakeUserPresets.json that cmk did not write
+        #[clap(short, long)]
+        force: bool,
+    },
+}
+
 #[derive(Debug, clap::Subcommand)]
 enum ToolchainCmd {
     /// Install a release (from a registry, or a local manifest).
@@ -318,26 +750,205 @@ enum CacheCmd {
 #[tokio::main]
 async fn main() -> Result<()> {
     let cli = Cli::parse();
+    style::set_color_choice(match cli.color {
+        ColorArg::Auto => ColorChoice::Auto,
+        ColorArg::Always => ColorChoice::Always,
+        ColorArg::Never => ColorChoice::Never,
+    });
+    if let Some(root) = cli.root.clone() {
+        cmk_project::set_project_root(root);
+    }
+    match run(cli).await {
+        Err(e) if e.downcast_ref::<UserAbort>().is_some() => {
+            eprintln!("{e}");
+            std::process::exit(130)
+        }
+        Err(e) => {
+            eprintln!("{}: {e:?}", style::red("Error").for_stderr());
+            if let Some(hint) = e.downcast_ref::<CmkError>().map(recovery_hint) {
+                eprintln!("{}: {hint}", style::cyan("hint").for_stderr());
+            }
+            std::process::exit(cmd::exit_code(&e))
+        }
+        Ok(()) => Ok(()),
+    }
+}
 
+async fn run(cli: Cli) -> Result<()> {
+    let format = if cli.json {
+        cmd::OutputFormat::Json
+    } else {
+        cmd::OutputFormat::Human
+    };
     if let Some(command) = cli.command {
         match command {
-            SubCommand::Add { name, project } => cmd::exec_add(name, project).await,
-            SubCommand::Update { project, yes } => cmd::exec_update(project, yes).await,
-            SubCommand::Get { name } => cmd::exec_get(name).await,
-            SubCommand::New { name, template } => cmd::exec_new(name, template).await,
+            SubCommand::Add {
+                name,
+                project,
+                inject,
+                alias,
+                pre_release,
+                force,
+                checksum,
+            } => cmd::exec_add(name, project, inject, alias, pre_release, force, checksum).await,
+            SubCommand::Update {
+                project,
+                yes,
+                fail_fast,
+            } => cmd::exec_update(project, yes, fail_fast).await,
+            SubCommand::Rollback { name } => cmd::exec_rollback(name).await,
+            SubCommand::Pin { build } => cmd::exec_pin(build).await,
+            SubCommand::Import { path, prefer } => cmd::exec_import(path, prefer).await,
+            SubCommand::Get { name, asset } => cmd::exec_get(name, asset).await,
+            SubCommand::New {
+                name,
+                template,
+                no_git,
+                std,
+                offline,
+                cmake_modules,
+                no_lock,
+                linker,
+                no_sanitizers,
+                sanitizers,
+            } => {
+                let options = cmd::NewOptions {
+                    name,
+                    template,
+                    no_git,
+                    std,
+                    offline,
+                    cmake_modules,
+                    no_lock,
+                    linker,
+                    no_sanitizers,
+                    sanitizers,
+                };
+                cmd::exec_new(options).await
+            }
             SubCommand::Run {
                 target,
                 args,
                 build,
-            } => cmd::exec_run(target, args, build).await,
+                release,
+                debug,
+                folder,
+                history,
+                timeout,
+                no_stdin,
+                args_file,
+                args_split,
+            } => {
+                let options = cmd::RunOptions {
+                    target,
+                    args,
+                    build,
+                    build_type: build_type(release, debug),
+                    folder,
+                    history,
+                    timeout,
+                    no_stdin,
+                    args_file,
+                    args_split,
+                };
+                cmd::exec_run(options, format).await
+            }
             SubCommand::Build {
-                target,
+                mut targets,
+                extra_targets,
                 build,
+                release,
+                debug,
                 interactive,
                 jobs,
-            } => cmd::exec_build(target, build, interactive, jobs).await,
-            SubCommand::BuildTU { name, build } => cmd::exec_build_tu(name, build).await,
-            SubCommand::Refresh { build } => cmd::exec_refresh(build).await,
+                lsp_symlink,
+                toolchain,
+                define,
+                folder,
+                progress,
+                config_only,
+                pattern,
+                sanitize,
+                tool_args,
+            } => {
+                targets.extend(extra_targets);
+                let options = cmd::BuildOptions {
+                    targets,
+                    build,
+                    build_type: build_type(release, debug),
+                    interactive,
+                    jobs,
+                    lsp_symlink,
+                    toolchain,
+                    definitions: define,
+                    folder,
+                    progress,
+                    config_only,
+                    pattern,
+                    sanitize,
+                    tool_args,
+                };
+                cmd::exec_build(options, format).await
+            }
+            SubCommand::Builds => cmd::exec_builds().await,
+            SubCommand::Bench {
+                name,
+                build,
+                target,
+                benchmark_filter,
+                benchmark_format,
+                benchmark_out,
+                args,
+            } => {
+                cmd::exec_bench(
+                    build,
+                    target.or(name),
+                    benchmark_filter,
+                    benchmark_format,
+                    benchmark_out,
+                    args,
+                )
+                .await
+            }
+            SubCommand::Which {
+                build,
+                build_first,
+                target,
+            } => cmd::exec_which(target, build, build_first).await,
+            SubCommand::Targets {
+                build,
+                target_type,
+                json,
+            } => cmd::exec_targets(build, target_type, json).await,
+            SubCommand::Graph { build, target } => cmd::exec_graph(build, target).await,
+            SubCommand::Env { build, run, export } => cmd::exec_env(build, run, export).await,
+            SubCommand::BuildTU {
+                name,
+                build,
+                print_command,
+                jobs,
+            } => cmd::exec_build_tu(name, build, print_command, jobs).await,
+            SubCommand::Configure {
+                build,
+                toolchain,
+                define,
+                sanitize,
+                generator,
+                config,
+                fresh,
+            } => {
+                cmd::exec_configure(cmd::ConfigureOptions {
+                    build,
+                    toolchain,
+                    definitions: define,
+                    sanitize,
+                    generator,
+                    build_type: config,
+                    fresh,
+                })
+                .await
+            }
+            SubCommand::Compdb { build, link, merge } => cmd::exec_compdb(build, link, merge).await,
             SubCommand::Fmt {
                 file,
                 all,
@@ -353,12 +964,21 @@ async fn main() -> Result<()> {
                 Ok(())
             }
             SubCommand::Init { force } => cmd::exec_init(force).await,
+            SubCommand::Doctor => cmd::doctor::run().await,
+            SubCommand::Status => cmd::status::run().await,
+            SubCommand::Alias { cmd } => match cmd {
+                AliasCmd::Rename { old, new } => cmd::exec_alias_rename(old, new).await,
+            },
+            SubCommand::Presets { cmd } => match cmd {
+                PresetsCmd::Export { force } => cmd::presets::export(force).await,
+            },
             SubCommand::Pkg { cmd } => match cmd {
                 PkgCmd::Option { name, opts } => cmd::exec_pkg_option(name, opts).await,
             },
             SubCommand::Lint {
                 build,
                 file,
+                target,
                 interactive,
                 all,
                 staged,
@@ -371,6 +991,7 @@ async fn main() -> Result<()> {
                 cmd::exec_lint(
                     build,
                     file,
+                    target,
                     interactive,
                     all,
                     staged,
@@ -387,7 +1008,61 @@ async fn main() -> Result<()> {
             SubCommand::Cache { cmd } => dispatch_cache(cmd),
         }
     } else {
-        cmd::exec_build(cli.target, cli.build, cli.interactive, cli.jobs).await
+        let options = cmd::BuildOptions {
+            targets: cli.target,
+            build: cli.build,
+            interactive: cli.interactive,
+            jobs: cli.jobs,
+            ..Default::default()
+        };
+        cmd::exec_build(options, format).await
+    }
+}
+
+/// What to do about a [`CmkError`], printed after it.
+fn recovery_hint(e: &CmkError) -> &'static str {
+    match e {
+        CmkError::NoGitRepository => {
+            "Run cmk inside a git repository, or name the project root with --root PATH (or CMK_ROOT)"
+        }
+        CmkError::NoBuildDirectory(_) => "Run 'cmake -S . -B build' to create a build directory",
+        CmkError::GitNotInstalled => "Install git, or name the project root with --root PATH",
+        CmkError::CmakeNotInstalled => "Install CMake and make sure `cmake` is on PATH",
+    }
+}
+
+/// The CMAKE_BUILD_TYPE `--release`/`--debug` ask for.
+fn build_type(release: bool, debug: bool) -> Option<&'static str> {
+    if release {
+        Some("Release")
+    } else if debug {
+        Some("Debug")
+    } else {
+        None
+    }
+}
+
+/// `500ms`, `10s`, `2m` or `1h`; a bare number is seconds.
+fn parse_duration(s: &str) -> Result<Duration, String> {
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (value, unit) = s.split_at(split);
+    let value: u64 = value
+        .parse()
+        .map_err(|_| format!("invalid duration '{s}'"))?;
+    let secs = |scale: u64| {
+        value
+            .checked_mul(scale)
+            .map(Duration::from_secs)
+            .ok_or_else(|| format!("duration '{s}' is too long"))
+    };
+    match unit {
+        "ms" => Ok(Duration::from_millis(value)),
+        "" | "s" => Ok(Duration::from_secs(value)),
+        "m" => secs(60),
+        "h" => secs(3600),
+        _ => Err(format!(
+            "invalid duration unit in '{s}' (use ms, s, m or h)"
+        )),
     }
 }
 
@@ -443,3 +1118,28 @@ fn dispatch_deps(_c: DepsCmd) -> Result<()> {
 fn dispatch_cache(_c: CacheCmd) -> Result<()> {
     anyhow::bail!("`cmk cache` lands alongside `cmk deps` in M5+")
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn durations_parse_with_units() {
+        assert_eq!(parse_duration("500ms"), Ok(Duration::from_millis(500)));
+        assert_eq!(parse_duration("10s"), Ok(Duration::from_secs(10)));
+        assert_eq!(parse_duration("2m"), Ok(Duration::from_secs(120)));
+        assert_eq!(parse_duration("1h"), Ok(Duration::from_secs(3600)));
+        assert_eq!(parse_duration("30"), Ok(Duration::from_secs(30)));
+    }
+
+    #[test]
+    fn bad_durations_are_rejected() {
+        assert!(parse_duration("").is_err());
+        assert!(parse_duration("s").is_err());
+        assert!(parse_duration("-1s").is_err());
+        assert!(parse_duration("1.5s").is_err());
+        assert!(parse_duration("3d").is_err());
+        assert!(parse_duration(&format!("{}h", u64::MAX)).is_err());
+        assert!(parse_duration(&format!("{}m", u64::MAX / 60 + 1)).is_err());
+    }
+}
@@ -6,22 +6,29 @@
 //! - `[deps.cmake]`           — declarative CMake-recipe deps (M5)
 //! - `[deps.custom]`          — build.sh-driven deps (M7)
 //! - `[build]`                — build dir defaults
+//! - `[cmake]`                — configure-time settings (toolchain file, `-D` definitions)
 //! - `[fmt]` / `[lint]`       — clang-format / clang-tidy filters
+//! - `[bench]`                — which executables `cmk bench` offers
+//! - `[[hooks.*]]`            — shell commands run after a build, around a run
 //!
 //! `[vars]` / `[env]` / `[env.*]` are **deleted** vs. schema=1; they were the
 //! manual `${DEPS_INSTALL}` glue replaced by automatic env injection from
 //! `[toolchain]` + `[deps.*]`. See design.md §4.1.
 
 use std::{
-    collections::HashMap,
+    collections::{BTreeMap, HashMap},
     path::{Path, PathBuf},
 };
 
-use anyhow::{Context, Result};
+use anyhow::{Context, Result, anyhow};
 use serde::Deserialize;
 
 const CONFIG_FILE_NAME: &str = ".cmk.toml";
 
+/// Points at a config file to use instead of `<project_root>/.cmk.toml`,
+/// e.g. to keep machine-specific settings out of the repo.
+const CONFIG_PATH_ENV: &str = "CMK_CONFIG";
+
 /// Top-level `.cmk.toml` schema. All sections optional.
 #[derive(Debug, Deserialize, Default)]
 pub struct CmkConfig {
@@ -36,28 +43,188 @@ pub struct CmkConfig {
     #[serde(default)]
     pub build: BuildConfig,
     #[serde(default)]
+    pub cmake: CMakeConfig,
+    #[serde(default)]
     pub fmt: FmtConfig,
     #[serde(default)]
     pub lint: LintConfig,
+    #[serde(default)]
+    pub bench: BenchConfig,
+    #[serde(default)]
+    pub hooks: HooksConfig,
+    /// Removed in schema 2; only parsed so `load` can flag leftovers.
+    #[serde(default)]
+    vars: Option<toml::Value>,
+    #[serde(default)]
+    env: Option<toml::Value>,
 }
 
 impl CmkConfig {
-    /// Load `.cmk.toml` from `project_root`. Returns default if absent.
+    /// The config file in effect: `$CMK_CONFIG` (absolute, or relative to
+    /// the current directory) if set, else `<project_root>/.cmk.toml`.
+    pub fn path(project_root: &Path) -> Result<PathBuf> {
+        match std::env::var_os(CONFIG_PATH_ENV) {
+            Some(path) => Ok(std::env::current_dir()?.join(path)),
+            None => Ok(project_root.join(CONFIG_FILE_NAME)),
+        }
+    }
+
+    /// Load the config from [`Self::path`]. A missing `.cmk.toml` yields the
+    /// default config; a missing `$CMK_CONFIG` file is an error.
     pub fn load(project_root: &Path) -> Result<Self> {
-        let path = project_root.join(CONFIG_FILE_NAME);
+        let path = Self::path(project_root)?;
         if !path.exists() {
+            if std::env::var_os(CONFIG_PATH_ENV).is_some() {
+                return Err(anyhow!(
+                    "{CONFIG_PATH_ENV} points at {}, which does not exist",
+                    path.display()
+                ));
+            }
             return Ok(Self::default());
         }
         let content = std::fs::read_to_string(&path)
             .with_context(|| format!("Failed to read {}", path.display()))?;
         let cfg: Self = toml::from_str(&content)
             .with_context(|| format!("Failed to parse {}", path.display()))?;
+        cfg.warn_removed_sections(&path);
         Ok(cfg)
     }
 
+    /// `[vars]` / `[env]` are silently ignored by serde otherwise, which
+    /// leaves users wondering why their `${DEPS_INSTALL}` paths never reach
+    /// cmake. Warn once per process until M4 turns this into a hard error.
+    fn warn_removed_sections(&self, path: &Path) {
+        static WARNED: std::sync::Once = std::sync::Once::new();
+        let mut removed = Vec::new();
+        if self.vars.is_some() {
+            removed.push("[vars]");
+        }
+        if self.env.is_some() {
+            removed.push("[env]");
+        }
+        if removed.is_empty() {
+            return;
+        }
+        WARNED.call_once(|| {
+            eprintln!(
+                "warning: {}: {} no longer applied; the build environment now comes from \
+                 [toolchain] / [deps.*] (see docs/design.md §4.1)",
+                path.display(),
+                removed.join(" / ")
+            );
+        });
+    }
+
     pub fn exists(project_root: &Path) -> bool {
-        project_root.join(CONFIG_FILE_NAME).exists()
+        Self::path(project_root).is_ok_and(|path| path.exists())
     }
+
+    /// Set `key = "value"` in `[section]` of the config file ([`Self::path`]),
+    /// creating the file or section as needed. Edits the text in place so comments and the
+    /// rest of the layout survive.
+    pub fn set_string(project_root: &Path, section: &str, key: &str, value: &str) -> Result<()> {
+        let path = Self::path(project_root)?;
+        let content = if path.exists() {
+            std::fs::read_to_string(&path)
+                .with_context(|| format!("Failed to read {}", path.display()))?
+        } else {
+            String::new()
+        };
+        let updated = upsert_string(&content, section, key, value);
+        std::fs::write(&path, updated)
+            .with_context(|| format!("Failed to write {}", path.display()))
+    }
+
+    /// Remove `key` from `[section]` of the config file, if it is there.
+    /// Like [`Self::set_string`], the rest of the text is left as is.
+    pub fn remove_key(project_root: &Path, section: &str, key: &str) -> Result<()> {
+        let path = Self::path(project_root)?;
+        if !path.exists() {
+            return Ok(());
+        }
+        let content = std::fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let Some(updated) = remove_entry(&content, section, key) else {
+            return Ok(());
+        };
+        std::fs::write(&path, updated)
+            .with_context(|| format!("Failed to write {}", path.display()))
+    }
+}
+
+/// `key` as written on the left of a TOML `key = value` line.
+fn toml_key(key: &str) -> String {
+    let bare = key
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+    if bare {
+        key.to_string()
+    } else {
+        toml::Value::String(key.to_string()).to_string()
+    }
+}
+
+/// The table a `[table]` header line opens, with any trailing comment and
+/// the whitespace TOML allows around its keys removed.
+fn header_name(line: &str) -> Option<String> {
+    let line = line
+        .split_once('#')
+        .map_or(line, |(before, _)| before)
+        .trim();
+    let name = line.strip_prefix('[')?.strip_suffix(']')?;
+    if name.starts_with('[') {
+        return None;
+    }
+    Some(name.split('.').map(str::trim).collect::<Vec<_>>().join("."))
+}
+
+/// Line index of `[section]`'s header, and of `key`'s line within it.
+fn find_entry(lines: &[String], section: &str, key: &str) -> Option<(usize, Option<usize>)> {
+    let key = toml_key(key);
+    let is_header = |line: &str| line.trim_start().starts_with('[');
+    let start = lines
+        .iter()
+        .position(|l| header_name(l).is_some_and(|name| name == section))?;
+    let end = lines[start + 1..]
+        .iter()
+        .position(|l| is_header(l))
+        .map_or(lines.len(), |i| start + 1 + i);
+    let existing = lines[start + 1..end]
+        .iter()
+        .position(|l| l.split_once('=').is_some_and(|(k, _)| k.trim() == key))
+        .map(|i| start + 1 + i);
+    Some((start, existing))
+}
+
+fn upsert_string(content: &str, section: &str, key: &str, value: &str) -> String {
+    let entry = format!(
+        "{} = {}",
+        toml_key(key),
+        toml::Value::String(value.to_string())
+    );
+    let mut lines: Vec<String> = content.lines().map(str::to_string).collect();
+    match find_entry(&lines, section, key) {
+        Some((_, Some(i))) => lines[i] = entry,
+        Some((start, None)) => lines.insert(start + 1, entry),
+        None => {
+            if lines.last().is_some_and(|l| !l.trim().is_empty()) {
+                lines.push(String::new());
+            }
+            lines.push(format!("[{section}]"));
+            lines.push(entry);
+        }
+    }
+    lines.join("\n") + "\n"
+}
+
+/// `content` without `key`'s line in `[section]`, or `None` if it has none.
+fn remove_entry(content: &str, section: &str, key: &str) -> Option<String> {
+    let mut lines: Vec<String> = content.lines().map(str::to_string).collect();
+    let (_, Some(i)) = find_entry(&lines, section, key)? else {
+        return None;
+    };
+    lines.remove(i);
+    Some(lines.join("\n") + "\n")
 }
 
 /// `[toolchain]` section. Wired in M4 to cmk-toolchain.
@@ -84,13 +251,88 @@ pub struct BuildConfig {
     /// Default build dir relative to project root.
     #[serde(default)]
     pub default: Option<String>,
+    /// Pass `-DCMAKE_EXPORT_COMPILE_COMMANDS=ON` when configuring a build dir
+    /// whose cache doesn't set it yet. Defaults to on.
+    #[serde(default)]
+    pub export_compile_commands: Option<bool>,
+    /// After `cmk build`, point `<root>/compile_commands.json` at the build
+    /// dir's copy for clangd/ccls.
+    #[serde(default)]
+    pub lsp_symlink: bool,
+    /// Reconfigure before `cmk build` when a `CMakeLists.txt` is newer than
+    /// the build dir's `CMakeCache.txt`, instead of only warning.
+    #[serde(default)]
+    pub auto_reconfigure: bool,
+}
+
+/// `[cmake]` section.
+#[derive(Debug, Deserialize, Default, Clone)]
+pub struct CMakeConfig {
+    /// `CMAKE_TOOLCHAIN_FILE` for configure, relative to the project root
+    /// unless absolute. Written by `--toolchain` so later runs reuse it.
+    #[serde(default)]
+    pub toolchain_file: Option<String>,
+    /// `[cmake.definitions]`: `-D` cache variables (`NAME` or `NAME:TYPE`
+    /// keys) passed on configure. Written by `-D` so later runs keep them.
+    #[serde(default)]
+    pub definitions: BTreeMap<String, String>,
+    /// Comma-separated `-fsanitize=` list, e.g. `"address,undefined"`.
+    /// Written by `--sanitize`, removed by `--sanitize none`.
+    #[serde(default)]
+    pub sanitize: Option<String>,
+    /// Linker passed as `-fuse-ld=<linker>` in the link flags, e.g. `"mold"`,
+    /// `"lld"` or `"gold"`.
+    #[serde(default)]
+    pub linker: Option<String>,
+    /// `CPM_SOURCE_CACHE` exported to cmake so CPM dependencies are shared
+    /// across projects, relative to the project root unless absolute.
+    /// `${PROJECT_ROOT}` / `${PROJECT_BUILD_ROOT}` are expanded. Defaults to
+    /// `~/.cache/CPM`; `""` leaves it unset.
+    #[serde(default)]
+    pub cpm_source_cache: Option<String>,
 }
 
+/// Where CPM keeps downloaded package sources across build dirs.
+const CPM_SOURCE_CACHE_ENV: &str = "CPM_SOURCE_CACHE";
+
+/// Compile and link flag variables [`CMakeConfig::apply_sanitizers`] sets.
+/// The `_INIT` variants only seed `CMAKE_<LANG>_FLAGS` and friends when the
+/// cache is created, so flags the user puts there are never overwritten.
+const SANITIZER_FLAG_VARS: &[&str] = &[
+    "CMAKE_C_FLAGS_INIT",
+    "CMAKE_CXX_FLAGS_INIT",
+    "CMAKE_EXE_LINKER_FLAGS_INIT",
+    "CMAKE_SHARED_LINKER_FLAGS_INIT",
+];
+
+/// Every cache variable cmk derives from `[cmake]` settings. Since they only
+/// take effect on a fresh cache, a build dir whose cached values differ is
+/// reconfigured from scratch.
+pub const MANAGED_FLAG_VARS: &[&str] = &[
+    "CMAKE_C_FLAGS_INIT",
+    "CMAKE_CXX_FLAGS_INIT",
+    "CMAKE_EXE_LINKER_FLAGS_INIT",
+    "CMAKE_SHARED_LINKER_FLAGS_INIT",
+    "CMAKE_MODULE_LINKER_FLAGS_INIT",
+];
+
+/// Link flag variables [`CMakeConfig::apply_linker`] sets, `_INIT` for the
+/// same reason as [`SANITIZER_FLAG_VARS`].
+const LINKER_FLAG_VARS: &[&str] = &[
+    "CMAKE_EXE_LINKER_FLAGS_INIT",
+    "CMAKE_SHARED_LINKER_FLAGS_INIT",
+    "CMAKE_MODULE_LINKER_FLAGS_INIT",
+];
+
 /// `[fmt]` section.
 #[derive(Debug, Deserialize, Default, Clone)]
 pub struct FmtConfig {
     #[serde(default)]
     pub ignore: Vec<String>,
+    /// File extensions (without the leading `.`) to format. Empty = every
+    /// C/C++ source and header extension cmk knows about.
+    #[serde(default)]
+    pub extensions: Vec<String>,
 }
 
 /// `[lint]` section.
@@ -106,12 +348,162 @@ pub struct LintConfig {
     pub extra_args: Vec<String>,
 }
 
+/// `[bench]` section.
+#[derive(Debug, Deserialize, Default, Clone)]
+pub struct BenchConfig {
+    /// Glob patterns for benchmark target names. Empty =
+    /// [`DEFAULT_BENCH_PATTERNS`].
+    #[serde(default)]
+    pub patterns: Vec<String>,
+}
+
+/// `[hooks]` section. Each list runs in declared order.
+#[derive(Debug, Deserialize, Default, Clone)]
+pub struct HooksConfig {
+    /// After a successful build, with the build environment.
+    #[serde(default)]
+    pub post_build: Vec<Hook>,
+    /// Before `cmk run` starts the target, with the run environment.
+    #[serde(default)]
+    pub pre_run: Vec<Hook>,
+    /// After the target exits, with the run environment plus
+    /// `CMK_RUN_EXIT_CODE`.
+    #[serde(default)]
+    pub post_run: Vec<Hook>,
+}
+
+/// One `[[hooks.*]]` entry: a shell command run from the project root with
+/// the build or run environment applied, plus `PROJECT_ROOT` and
+/// `PROJECT_BUILD_ROOT` (the build dir) for the shell to expand.
+#[derive(Debug, Deserialize, Clone)]
+pub struct Hook {
+    pub command: String,
+    /// Only run when this target is involved. Unset = always.
+    #[serde(default)]
+    pub target: Option<String>,
+    /// Warn instead of failing the command when the hook exits non-zero.
+    #[serde(default)]
+    pub allow_failure: bool,
+}
+
+impl Hook {
+    /// Whether the hook runs for a build or run of `targets`. Building
+    /// `all` counts as building every target.
+    pub fn applies_to(&self, targets: &[String]) -> bool {
+        self.target
+            .as_ref()
+            .is_none_or(|target| targets.iter().any(|t| t == target || t == "all"))
+    }
+
+    /// `command` with the project placeholders expanded, for display.
+    pub fn expanded_command(&self, project_root: &Path, build_dir: &Path) -> String {
+        expand_project_vars(&self.command, project_root, build_dir)
+    }
+}
+
+/// Replace `${PROJECT_ROOT}` and `${PROJECT_BUILD_ROOT}` in `text` with the
+/// project root and build dir.
+pub fn expand_project_vars(text: &str, project_root: &Path, build_dir: &Path) -> String {
+    text.replace("${PROJECT_ROOT}", &project_root.to_string_lossy())
+        .replace(PROJECT_BUILD_ROOT_VAR, &build_dir.to_string_lossy())
+}
+
+/// [`expand_project_vars`] for when the build dir may not be known. Text that
+/// needs `${PROJECT_BUILD_ROOT}` without one is an error rather than being
+/// handed to a child process with the placeholder still in it.
+pub fn try_expand_project_vars(
+    text: &str,
+    project_root: &Path,
+    build_dir: Option<&Path>,
+) -> Result<String> {
+    match build_dir {
+        Some(build_dir) => Ok(expand_project_vars(text, project_root, build_dir)),
+        None if text.contains(PROJECT_BUILD_ROOT_VAR) => Err(anyhow!(
+            "'{text}' uses {PROJECT_BUILD_ROOT_VAR} but no build directory is selected"
+        )),
+        None => Ok(text.replace("${PROJECT_ROOT}", &project_root.to_string_lossy())),
+    }
+}
+
+const PROJECT_BUILD_ROOT_VAR: &str = "${PROJECT_BUILD_ROOT}";
+
+/// Benchmark target names `cmk bench` recognizes by default.
+pub const DEFAULT_BENCH_PATTERNS: &[&str] = &["bench_*", "*_bench", "*_benchmark"];
+
 // Convenience load fns kept for callers that only need one section.
 
 impl BuildConfig {
     pub fn load(project_root: &Path) -> Result<Self> {
         Ok(CmkConfig::load(project_root)?.build)
     }
+
+    pub fn export_compile_commands(&self) -> bool {
+        self.export_compile_commands.unwrap_or(true)
+    }
+}
+
+impl CMakeConfig {
+    pub fn load(project_root: &Path) -> Result<Self> {
+        Ok(CmkConfig::load(project_root)?.cmake)
+    }
+
+    /// `toolchain_file` resolved against `project_root`.
+    pub fn toolchain_file(&self, project_root: &Path) -> Option<PathBuf> {
+        self.toolchain_file.as_ref().map(|p| project_root.join(p))
+    }
+
+    /// `cpm_source_cache` expanded and resolved against `project_root`, or
+    /// the default `~/.cache/CPM` when not configured. Fails when it uses
+    /// `${PROJECT_BUILD_ROOT}` and no `build_dir` is known.
+    pub fn cpm_source_cache(
+        &self,
+        project_root: &Path,
+        build_dir: Option<&Path>,
+    ) -> Result<Option<PathBuf>> {
+        Ok(match self.cpm_source_cache.as_deref() {
+            Some("") => None,
+            Some(path) => {
+                Some(project_root.join(try_expand_project_vars(path, project_root, build_dir)?))
+            }
+            None => dirs::home_dir().map(|home| home.join(".cache/CPM")),
+        })
+    }
+
+    /// Append `-fsanitize=<sanitize>` to the initial C/C++ compile and link
+    /// flags in `definitions`, after any value given there. Without
+    /// `sanitize` nothing is touched.
+    pub fn apply_sanitizers(&self, definitions: &mut BTreeMap<String, String>) {
+        let Some(sanitize) = self.sanitize.as_deref().filter(|s| !s.is_empty()) else {
+            return;
+        };
+        for var in SANITIZER_FLAG_VARS {
+            let value = definitions.entry(var.to_string()).or_default();
+            if !value.is_empty() {
+                value.push(' ');
+            }
+            value.push_str(&format!("-fsanitize={sanitize}"));
+        }
+    }
+
+    /// Append `-fuse-ld=<linker>` to the initial link flags in `definitions`,
+    /// after any value given there. Without `linker` nothing is touched.
+    pub fn apply_linker(&self, definitions: &mut BTreeMap<String, String>) {
+        let Some(linker) = self.linker.as_deref().filter(|l| !l.is_empty()) else {
+            return;
+        };
+        for var in LINKER_FLAG_VARS {
+            let value = definitions.entry(var.to_string()).or_default();
+            if !value.is_empty() {
+                value.push(' ');
+            }
+            value.push_str(&format!("-fuse-ld={linker}"));
+        }
+    }
+}
+
+/// The executable the compiler driver runs for `-fuse-ld=<linker>`.
+pub fn linker_executable(linker: &str) -> String {
+    format!("ld.{linker}")
 }
 
 impl FmtConfig {
@@ -126,27 +518,75 @@ impl LintConfig {
     }
 }
 
+impl BenchConfig {
+    pub fn load(project_root: &Path) -> Result<Self> {
+        Ok(CmkConfig::load(project_root)?.bench)
+    }
+
+    /// `patterns`, or the defaults when none are configured.
+    pub fn patterns(&self) -> Vec<String> {
+        if self.patterns.is_empty() {
+            DEFAULT_BENCH_PATTERNS
+                .iter()
+                .map(|p| p.to_string())
+                .collect()
+        } else {
+            self.patterns.clone()
+        }
+    }
+}
+
 /// Environment injected into cmake / ninja / target binary invocations.
 ///
-/// **M0 placeholder**: empty. M4 fills `CC`/`CXX`/`PATH` prefix from
+/// Build commands get `CPM_SOURCE_CACHE` (see
+/// [`CMakeConfig::cpm_source_cache`]) unless the caller's environment
+/// already sets it. Otherwise a placeholder: M4 fills `CC`/`CXX`/`PATH` prefix from
 /// `[toolchain]`; M5 fills `CMAKE_PREFIX_PATH`/`PKG_CONFIG_PATH`/`LD_LIBRARY_PATH`
 /// from `[deps.*]` install prefix.
 #[derive(Debug, Default, Clone)]
 pub struct BuildEnv {
     project_root: PathBuf,
+    cmake: CMakeConfig,
 }
 
 impl BuildEnv {
     pub fn load(project_root: &Path) -> Result<Self> {
-        let _ = CmkConfig::load(project_root)?;
+        let config = CmkConfig::load(project_root)?;
         Ok(Self {
             project_root: project_root.to_path_buf(),
+            cmake: config.cmake,
         })
     }
 
-    /// Env vars for build commands (cmake/ninja). M0: empty.
-    pub fn build_env(&self, _build_dir: Option<&Path>) -> HashMap<String, String> {
-        HashMap::new()
+    /// Env vars for build commands (cmake/ninja): `CPM_SOURCE_CACHE`.
+    ///
+    /// A value that can't be resolved without a build dir is left out with a
+    /// warning, so callers should pass `build_dir` whenever they have one.
+    pub fn build_env(&self, build_dir: Option<&Path>) -> HashMap<String, String> {
+        let mut env = HashMap::new();
+        if std::env::var_os(CPM_SOURCE_CACHE_ENV).is_some() {
+            return env;
+        }
+        match self.cmake.cpm_source_cache(&self.project_root, build_dir) {
+            Ok(Some(cache)) => {
+                env.insert(
+                    CPM_SOURCE_CACHE_ENV.to_string(),
+                    cache.to_string_lossy().into_owned(),
+                );
+            }
+            Ok(None) => {}
+            Err(e) => eprintln!("warning: [cmake] cpm_source_cache not exported: {e}"),
+        }
+        env
+    }
+
+    /// The CPM source cache build commands in `build_dir` see: the inherited
+    /// `$CPM_SOURCE_CACHE` if set, else the one cmk exports.
+    pub fn cpm_source_cache(&self, build_dir: Option<&Path>) -> Result<Option<PathBuf>> {
+        match std::env::var_os(CPM_SOURCE_CACHE_ENV) {
+            Some(path) => Ok(Some(PathBuf::from(path))),
+            None => self.cmake.cpm_source_cache(&self.project_root, build_dir),
+        }
     }
 
     /// Env vars for `cmk run <target>`. M0: empty.
@@ -172,3 +612,230 @@ impl BuildEnv {
         &self.project_root
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sanitizers_extend_flag_definitions() {
+        let mut config = CMakeConfig {
+            sanitize: Some("address,undefined".to_string()),
+            ..Default::default()
+        };
+        let mut definitions = BTreeMap::from([
+            ("CMAKE_CXX_FLAGS".to_string(), "-Wall".to_string()),
+            ("CMAKE_CXX_FLAGS_INIT".to_string(), "-O1".to_string()),
+        ]);
+        config.apply_sanitizers(&mut definitions);
+        assert_eq!(definitions["CMAKE_CXX_FLAGS"], "-Wall");
+        assert_eq!(
+            definitions["CMAKE_CXX_FLAGS_INIT"],
+            "-O1 -fsanitize=address,undefined"
+        );
+        assert_eq!(
+            definitions["CMAKE_EXE_LINKER_FLAGS_INIT"],
+            "-fsanitize=address,undefined"
+        );
+
+        config.sanitize = Some(String::new());
+        let mut definitions = BTreeMap::new();
+        config.apply_sanitizers(&mut definitions);
+        assert!(definitions.is_empty());
+
+        config.sanitize = None;
+        let mut definitions = BTreeMap::new();
+        config.apply_sanitizers(&mut definitions);
+        assert!(definitions.is_empty());
+    }
+
+    #[test]
+    fn linker_extends_link_flags() {
+        let config = CMakeConfig {
+            sanitize: Some("address".to_string()),
+            linker: Some("mold".to_string()),
+            ..Default::default()
+        };
+        let mut definitions = BTreeMap::new();
+        config.apply_sanitizers(&mut definitions);
+        config.apply_linker(&mut definitions);
+        assert_eq!(
+            definitions["CMAKE_EXE_LINKER_FLAGS_INIT"],
+            "-fsanitize=address -fuse-ld=mold"
+        );
+        assert_eq!(
+            definitions["CMAKE_MODULE_LINKER_FLAGS_INIT"],
+            "-fuse-ld=mold"
+        );
+        assert!(!definitions.contains_key("CMAKE_EXE_LINKER_FLAGS"));
+        assert_eq!(definitions["CMAKE_CXX_FLAGS_INIT"], "-fsanitize=address");
+        assert_eq!(linker_executable("lld"), "ld.lld");
+    }
+
+    #[test]
+    fn post_build_hooks_parse_in_order() {
+        let config: CmkConfig = toml::from_str(
+            r#"
+            [[hooks.post_build]]
+            command = "cp -r assets ${PROJECT_BUILD_ROOT}/assets"
+
+            [[hooks.post_build]]
+            command = "strip ${PROJECT_BUILD_ROOT}/app"
+            target = "app"
+            allow_failure = true
+
+            [[hooks.post_run]]
+            command = "echo $CMK_RUN_EXIT_CODE"
+            "#,
+        )
+        .unwrap();
+        assert!(config.hooks.pre_run.is_empty());
+        assert_eq!(config.hooks.post_run.len(), 1);
+        let hooks = &config.hooks.post_build;
+        assert_eq!(hooks.len(), 2);
+        assert_eq!(
+            hooks[0].expanded_command(Path::new("/p"), Path::new("/p/build")),
+            "cp -r assets /p/build/assets"
+        );
+        assert!(hooks[0].applies_to(&["tool".to_string()]));
+        assert!(hooks[1].allow_failure);
+        assert!(hooks[1].applies_to(&["app".to_string()]));
+        assert!(hooks[1].applies_to(&["all".to_string()]));
+        assert!(!hooks[1].applies_to(&["tool".to_string()]));
+    }
+
+    #[test]
+    fn cpm_source_cache_resolves_against_root() {
+        let root = Path::new("/project");
+        let mut config = CMakeConfig {
+            cpm_source_cache: Some(".cpm".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            config.cpm_source_cache(root, None).unwrap(),
+            Some(PathBuf::from("/project/.cpm"))
+        );
+        config.cpm_source_cache = Some("/var/cache/cpm".to_string());
+        assert_eq!(
+            config.cpm_source_cache(root, None).unwrap(),
+            Some(PathBuf::from("/var/cache/cpm"))
+        );
+        config.cpm_source_cache = Some(String::new());
+        assert_eq!(config.cpm_source_cache(root, None).unwrap(), None);
+    }
+
+    #[test]
+    fn cpm_source_cache_expands_project_vars() {
+        let root = Path::new("/project");
+        let mut config = CMakeConfig {
+            cpm_source_cache: Some("${PROJECT_BUILD_ROOT}/_cpm".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            config
+                .cpm_source_cache(root, Some(Path::new("/project/build")))
+                .unwrap(),
+            Some(PathBuf::from("/project/build/_cpm"))
+        );
+        let err = config.cpm_source_cache(root, None).unwrap_err();
+        assert!(err.to_string().contains("no build directory"), "{err}");
+
+        config.cpm_source_cache = Some("${PROJECT_ROOT}/.cpm".to_string());
+        assert_eq!(
+            config.cpm_source_cache(root, None).unwrap(),
+            Some(PathBuf::from("/project/.cpm"))
+        );
+    }
+
+    #[test]
+    fn project_vars_without_build_dir() {
+        let root = Path::new("/p");
+        assert_eq!(
+            try_expand_project_vars("${PROJECT_ROOT}/x", root, None).unwrap(),
+            "/p/x"
+        );
+        assert!(try_expand_project_vars("${PROJECT_BUILD_ROOT}/x", root, None).is_err());
+        assert_eq!(
+            try_expand_project_vars("${PROJECT_BUILD_ROOT}/x", root, Some(Path::new("/p/b")))
+                .unwrap(),
+            "/p/b/x"
+        );
+    }
+
+    #[test]
+    fn upsert_string_preserves_layout() {
+        let content = "# project config\n\
+                       [build]\n\
+                       default = \"build\"\n\
+                       \n\
+                       [cmake]\n\
+                       # toolchain_file = \"old\"\n\
+                       toolchain_file = \"a.cmake\"\n\
+                       \n\
+                       [fmt]\n\
+                       ignore = []\n";
+        let updated = upsert_string(content, "cmake", "toolchain_file", "b.cmake");
+        assert_eq!(updated, content.replace("= \"a.cmake\"", "= \"b.cmake\""));
+
+        let added = upsert_string(
+            "[build]\ndefault = \"build\"\n",
+            "cmake",
+            "toolchain_file",
+            "b.cmake",
+        );
+        assert_eq!(
+            added,
+            "[build]\ndefault = \"build\"\n\n[cmake]\ntoolchain_file = \"b.cmake\"\n"
+        );
+
+        let quoted = upsert_string("", "cmake.definitions", "FOO:BOOL", "ON");
+        assert_eq!(quoted, "[cmake.definitions]\n\"FOO:BOOL\" = \"ON\"\n");
+        let replaced = upsert_string(&quoted, "cmake.definitions", "FOO:BOOL", "OFF");
+        assert_eq!(replaced, "[cmake.definitions]\n\"FOO:BOOL\" = \"OFF\"\n");
+    }
+
+    #[test]
+    fn remove_entry_only_touches_the_key() {
+        let content = "[cmake]\n\
+                       # sanitize = \"address\"\n\
+                       sanitize = \"address\"\n\
+                       linker = \"mold\"\n\
+                       \n\
+                       [build]\n\
+                       sanitize = \"x\"\n";
+        assert_eq!(
+            remove_entry(content, "cmake", "sanitize").as_deref(),
+            Some(
+                content
+                    .replace("sanitize = \"address\"\nlinker", "linker")
+                    .as_str()
+            )
+        );
+        assert_eq!(remove_entry(content, "cmake", "toolchain_file"), None);
+        assert_eq!(remove_entry(content, "fmt", "sanitize"), None);
+    }
+
+    #[test]
+    fn upsert_string_finds_commented_and_nested_headers() {
+        let commented = "[cmake]  # toolchain settings\nlinker = \"mold\"\n";
+        let updated = upsert_string(commented, "cmake", "toolchain_file", "a.cmake");
+        assert_eq!(
+            updated,
+            "[cmake]  # toolchain settings\ntoolchain_file = \"a.cmake\"\nlinker = \"mold\"\n"
+        );
+        assert!(toml::from_str::<CmkConfig>(&updated).is_ok());
+
+        let nested = "[cmake.definitions]\nFOO = \"1\"\n\n[cmake]\nlinker = \"mold\"\n";
+        let updated = upsert_string(nested, "cmake", "toolchain_file", "a.cmake");
+        assert_eq!(
+            updated,
+            nested.replace("[cmake]\n", "[cmake]\ntoolchain_file = \"a.cmake\"\n")
+        );
+        let config: CmkConfig = toml::from_str(&updated).unwrap();
+        assert_eq!(config.cmake.toolchain_file.as_deref(), Some("a.cmake"));
+        assert_eq!(
+            remove_entry(&updated, "cmake", "toolchain_file").as_deref(),
+            Some(nested)
+        );
+    }
+}
@@ -11,11 +11,16 @@ use crate::error::Result;
 ///   "github:my-org/cmk-dist",
 ///   "https://mirror.internal.example.com/cmk",
 /// ]
+/// default_build_dir = "build"
 /// ```
 #[derive(Debug, Default, Clone, Serialize, Deserialize)]
 pub struct Config {
     #[serde(default)]
     pub registries: Vec<String>,
+    /// Build dir picked in every project that has one by that name, when
+    /// neither the cwd nor `[build] default` in `.cmk.toml` decides.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default_build_dir: Option<String>,
 }
 
 impl Config {
@@ -4,11 +4,16 @@ pub mod manifest;
 pub mod platform;
 pub mod process;
 pub mod store;
+pub mod style;
 pub mod version;
 
 pub use config::Config;
 pub use error::{Error, Result};
 pub use manifest::{Manifest, Package, Platform as PlatformEntry, Release};
 pub use platform::current_platform;
-pub use process::{completing_read, confirm, wait_with_cancel};
+pub use process::{
+    ChildFailed, TimedOut, UserAbort, completing_read, completing_read_multi,
+    completing_read_optional, completing_read_with_preview, confirm, wait_foreground,
+    wait_foreground_timeout, wait_with_cancel,
+};
 pub use store::{InstalledIndex, InstalledPackage, InstalledVersion, Store};
@@ -1,5 +1,5 @@
 use anyhow::Result;
-use std::{cmp::min, io::Write, process::Stdio};
+use std::{cmp::min, collections::HashMap, io::Write, process::Stdio, time::Duration};
 use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
 use tokio::process::Command;
 
@@ -27,27 +27,209 @@ pub async fn wait_with_cancel(
     }
 }
 
-pub async fn completing_read(elements: &[String]) -> Result<String> {
-    let height = min(elements.len(), 10) + 2;
-    let mut fzf = Command::new("fzf")
-        .stdin(Stdio::piped())
+/// Wait for a child that shares cmk's terminal, such as the target started
+/// by `cmk run`. Ctrl-C already reaches the child through the foreground
+/// process group, so cmk swallows its own SIGINT and keeps waiting: the
+/// child can shut down gracefully and its exit status is what is returned.
+pub async fn wait_foreground(
+    child: &mut tokio::process::Child,
+) -> Result<std::process::ExitStatus> {
+    let status = child.wait();
+    tokio::pin!(status);
+    loop {
+        tokio::select! {
+            status = &mut status => return Ok(status?),
+            _ = tokio::signal::ctrl_c() => {}
+        }
+    }
+}
+
+/// How long a timed-out child gets to exit after SIGTERM before SIGKILL.
+const TERMINATE_GRACE: Duration = Duration::from_secs(5);
+
+/// [`wait_foreground`] with an optional deadline. When it passes first the
+/// child gets SIGTERM, then SIGKILL if it is still running after a short
+/// grace period, and the result is a [`TimedOut`] error.
+pub async fn wait_foreground_timeout(
+    child: &mut tokio::process::Child,
+    timeout: Option<Duration>,
+) -> Result<std::process::ExitStatus> {
+    let Some(timeout) = timeout else {
+        return wait_foreground(child).await;
+    };
+    if let Ok(status) = tokio::time::timeout(timeout, wait_foreground(child)).await {
+        return status;
+    }
+    #[cfg(unix)]
+    if let Some(pid) = child.id() {
+        // SAFETY: plain kill(2) on the pid of a child we have not reaped yet.
+        unsafe { libc::kill(pid as libc::pid_t, libc::SIGTERM) };
+        if tokio::time::timeout(TERMINATE_GRACE, child.wait())
+            .await
+            .is_ok()
+        {
+            return Err(TimedOut(timeout).into());
+        }
+    }
+    child.kill().await.ok();
+    Err(TimedOut(timeout).into())
+}
+
+/// A child was stopped for running longer than its timeout. `main` exits
+/// with 124, like timeout(1).
+#[derive(Debug, thiserror::Error)]
+#[error("timed out after {0:?}")]
+pub struct TimedOut(pub Duration);
+
+/// A child process exited unsuccessfully. `main` exits with the same code
+/// (128 + signal number when the child was killed by a signal).
+#[derive(Debug, thiserror::Error)]
+#[error("{0}")]
+pub struct ChildFailed(pub std::process::ExitStatus);
+
+impl ChildFailed {
+    pub fn exit_code(&self) -> i32 {
+        #[cfg(unix)]
+        {
+            use std::os::unix::process::ExitStatusExt;
+            if let Some(signal) = self.0.signal() {
+                return 128 + signal;
+            }
+        }
+        self.0.code().unwrap_or(1)
+    }
+}
+
+/// The user dismissed the fuzzy finder (Esc / Ctrl-C). `main` reports this
+/// as a plain "Aborted." instead of an error trace.
+#[derive(Debug, thiserror::Error)]
+#[error("Aborted.")]
+pub struct UserAbort;
+
+/// Pick one of `elements` with fzf. Fails with [`UserAbort`] when the user
+/// cancels the picker.
+///
+/// `previews` maps an element to the text shown in fzf's preview pane while
+/// it is highlighted; elements without an entry get an empty pane.
+pub async fn completing_read(
+    elements: &[String],
+    previews: Option<&HashMap<String, String>>,
+) -> Result<String> {
+    run_fzf(elements, &[], previews.map(Preview::Text))
+        .await?
+        .and_then(|lines| lines.into_iter().next())
+        .ok_or_else(|| UserAbort.into())
+}
+
+/// Like [`completing_read`], but the preview pane shows the output of
+/// `preview_cmd`, a shell command in which fzf replaces `{}` with the
+/// highlighted element (e.g. `ninja -C build -t query {}`). `prompt` is shown
+/// before the query.
+pub async fn completing_read_with_preview(
+    elements: &[String],
+    prompt: &str,
+    preview_cmd: Option<&str>,
+) -> Result<String> {
+    let prompt = format!("{prompt}> ");
+    run_fzf(
+        elements,
+        &["--prompt", &prompt],
+        preview_cmd.map(Preview::Command),
+    )
+    .await?
+    .and_then(|lines| lines.into_iter().next())
+    .ok_or_else(|| UserAbort.into())
+}
+
+/// Like [`completing_read`], but a cancelled picker yields `Ok(None)`.
+pub async fn completing_read_optional(elements: &[String]) -> Result<Option<String>> {
+    Ok(run_fzf(elements, &[], None)
+        .await?
+        .and_then(|lines| lines.into_iter().next()))
+}
+
+/// Pick any number of `elements` with `fzf --multi` (Tab to mark). Fails with
+/// [`UserAbort`] when the user cancels the picker.
+pub async fn completing_read_multi(elements: &[String]) -> Result<Vec<String>> {
+    run_fzf(elements, &["--multi"], None)
+        .await?
+        .ok_or_else(|| UserAbort.into())
+}
+
+/// What fzf's preview pane shows.
+enum Preview<'a> {
+    /// Fixed text per element.
+    Text(&'a HashMap<String, String>),
+    /// A shell command run by fzf for the highlighted element.
+    Command(&'a str),
+}
+
+/// Feed `elements` to fzf and return the selected lines, or `None` when the
+/// picker was cancelled.
+///
+/// With [`Preview::Text`], each preview is written to its own file in a temp
+/// dir and lines are fed as `<index>\t<element>`; fzf hides the index column
+/// and `cat`s the matching file, and the index maps the selection back.
+async fn run_fzf(
+    elements: &[String],
+    extra_args: &[&str],
+    preview: Option<Preview<'_>>,
+) -> Result<Option<Vec<String>>> {
+    let mut height = min(elements.len(), 10) + 2;
+    if preview.is_some() {
+        // Leave the preview pane room to show more than a line or two.
+        height = height.max(12);
+    }
+    let mut cmd = Command::new("fzf");
+    cmd.stdin(Stdio::piped())
         .stdout(Stdio::piped())
         .args(["--height", &height.to_string()])
-        .spawn()?;
+        .args(extra_args);
+    let preview_dir = match preview {
+        Some(Preview::Text(previews)) => {
+            let dir = tempfile::tempdir()?;
+            for (i, element) in elements.iter().enumerate() {
+                let text = previews.get(element).map(String::as_str).unwrap_or("");
+                std::fs::write(dir.path().join(i.to_string()), text)?;
+            }
+            cmd.args(["--delimiter", "\t", "--with-nth", "2.."])
+                .arg("--preview")
+                .arg(format!("cat '{}'/{{1}}", dir.path().display()));
+            Some(dir)
+        }
+        Some(Preview::Command(preview_cmd)) => {
+            cmd.args(["--preview", preview_cmd]);
+            None
+        }
+        None => None,
+    };
+    let mut fzf = cmd.spawn()?;
     let mut child_stdin = fzf.stdin.take().unwrap();
-    for element in elements {
+    for (i, element) in elements.iter().enumerate() {
+        if preview_dir.is_some() {
+            child_stdin.write_all(format!("{i}\t").as_bytes()).await?;
+        }
         child_stdin.write_all(element.as_bytes()).await?;
         child_stdin.write_all(b"\n").await?;
     }
     drop(child_stdin);
     let output = fzf.wait_with_output().await?;
-    if !output.status.success() {
-        // fzf exits 130 on Ctrl+C, 1 on Esc/no-match — treat all as user cancellation
-        std::process::exit(130);
+    match output.status.code() {
+        Some(0) => {}
+        // 130: Ctrl+C / Esc, 1: accepted with no match — both are cancellation.
+        Some(130) | Some(1) => return Ok(None),
+        _ => return Err(anyhow::anyhow!("fzf failed: {}", output.status)),
     }
-    let mut stdout = output.stdout;
-    if stdout.ends_with(b"\n") {
-        stdout.pop();
+    let stdout = String::from_utf8(output.stdout)?;
+    let lines = stdout.lines().filter(|line| !line.is_empty());
+    if preview_dir.is_none() {
+        return Ok(Some(lines.map(str::to_string).collect()));
     }
-    Ok(String::from_utf8(stdout)?)
+    Ok(Some(
+        lines
+            .filter_map(|line| line.split_once('\t'))
+            .filter_map(|(index, _)| index.parse::<usize>().ok())
+            .filter_map(|index| elements.get(index).cloned())
+            .collect(),
+    ))
 }
@@ -0,0 +1,138 @@
+//! Terminal colors for status output. Colors are on by default only when
+//! the stream is a terminal and `NO_COLOR` is unset; `cmk --color` overrides
+//! that through [`set_color_choice`].
+
+use std::ffi::OsStr;
+use std::fmt::{self, Display};
+use std::io::IsTerminal;
+use std::sync::atomic::{AtomicU8, Ordering};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ColorChoice {
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+static CHOICE: AtomicU8 = AtomicU8::new(ColorChoice::Auto as u8);
+
+pub fn set_color_choice(choice: ColorChoice) {
+    CHOICE.store(choice as u8, Ordering::Relaxed);
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stream {
+    Stdout,
+    Stderr,
+}
+
+/// Whether output written to `stream` should be colored.
+pub fn color_enabled(stream: Stream) -> bool {
+    let choice = match CHOICE.load(Ordering::Relaxed) {
+        c if c == ColorChoice::Always as u8 => ColorChoice::Always,
+        c if c == ColorChoice::Never as u8 => ColorChoice::Never,
+        _ => ColorChoice::Auto,
+    };
+    let tty = || match stream {
+        Stream::Stdout => std::io::stdout().is_terminal(),
+        Stream::Stderr => std::io::stderr().is_terminal(),
+    };
+    decide_color(choice, tty, std::env::var_os("NO_COLOR").as_deref())
+}
+
+/// [`color_enabled`] for a `choice`, whether the stream is a terminal (only
+/// asked in `Auto` mode) and the value of `NO_COLOR`, which only counts
+/// when non-empty.
+fn decide_color(choice: ColorChoice, tty: impl FnOnce() -> bool, no_color: Option<&OsStr>) -> bool {
+    match choice {
+        ColorChoice::Always => true,
+        ColorChoice::Never => false,
+        ColorChoice::Auto => no_color.is_none_or(|v| v.is_empty()) && tty(),
+    }
+}
+
+/// `value` wrapped in an SGR color when its stream allows it.
+#[derive(Debug, Clone, Copy)]
+pub struct Styled<T> {
+    value: T,
+    code: &'static str,
+    stream: Stream,
+}
+
+impl<T> Styled<T> {
+    /// Decide coloring by stderr instead of stdout.
+    pub fn for_stderr(mut self) -> Self {
+        self.stream = Stream::Stderr;
+        self
+    }
+}
+
+impl<T: Display> Display for Styled<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if color_enabled(self.stream) {
+            write!(f, "\x1b[{}m{}\x1b[0m", self.code, self.value)
+        } else {
+            self.value.fmt(f)
+        }
+    }
+}
+
+fn styled<T>(value: T, code: &'static str) -> Styled<T> {
+    Styled {
+        value,
+        code,
+        stream: Stream::Stdout,
+    }
+}
+
+pub fn red<T>(value: T) -> Styled<T> {
+    styled(value, "31")
+}
+
+pub fn green<T>(value: T) -> Styled<T> {
+    styled(value, "32")
+}
+
+pub fn yellow<T>(value: T) -> Styled<T> {
+    styled(value, "33")
+}
+
+pub fn cyan<T>(value: T) -> Styled<T> {
+    styled(value, "36")
+}
+
+pub fn bold<T>(value: T) -> Styled<T> {
+    styled(value, "1")
+}
+
+/// The `->` between an old and a new version.
+pub fn arrow() -> Styled<&'static str> {
+    yellow("->")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn auto_colors_only_terminals_without_no_color() {
+        let auto = |tty: bool, no_color: Option<&str>| {
+            decide_color(ColorChoice::Auto, || tty, no_color.map(OsStr::new))
+        };
+        assert!(auto(true, None));
+        assert!(auto(true, Some("")));
+        assert!(!auto(true, Some("1")));
+        assert!(!auto(false, None));
+    }
+
+    #[test]
+    fn explicit_choice_overrides_terminal_and_no_color() {
+        assert!(decide_color(
+            ColorChoice::Always,
+            || false,
+            Some(OsStr::new("1"))
+        ));
+        assert!(!decide_color(ColorChoice::Never, || true, None));
+    }
+}
@@ -1,9 +1,11 @@
 use anyhow::{Context, Result, anyhow};
+use chrono::{DateTime, Utc};
+use cmk_core::style;
 use futures::future::join_all;
 use serde::{Deserialize, Serialize};
 use sha2::Digest;
 use std::{
-    collections::HashMap,
+    collections::{BTreeMap, btree_map::Entry},
     fmt::{self, Display},
     path::PathBuf,
 };
@@ -26,10 +28,20 @@ pub fn cpm_info_path() -> Result<PathBuf> {
     Ok(config_dir()?.join("cpm.json"))
 }
 
+/// Days the cached CPM version is trusted before `cmk new` asks GitHub
+/// again; `0` re-queries every time.
+pub const CPM_TTL_ENV: &str = "CMK_CPM_TTL_DAYS";
+
+/// Default for [`CPM_TTL_ENV`].
+const DEFAULT_CPM_TTL_DAYS: i64 = 7;
+
 #[derive(Debug, Serialize, Deserialize, Hash, Clone)]
 pub struct Package {
     pub owner: String,
     pub repo: String,
+    /// Track pre-releases too, not just the latest stable release.
+    #[serde(default)]
+    pub allow_prerelease: bool,
 }
 
 impl Display for Package {
@@ -38,18 +50,117 @@ impl Display for Package {
     }
 }
 
+/// A tracked release of a package. Older indexes stored just the tag string;
+/// those still load, with the other fields unset.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(from = "ReleaseRepr")]
+pub struct ReleaseInfo {
+    pub tag: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub published_at: Option<DateTime<Utc>>,
+    /// Release notes.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub body: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ReleaseRepr {
+    Tag(String),
+    Full {
+        tag: String,
+        #[serde(default)]
+        published_at: Option<DateTime<Utc>>,
+        #[serde(default)]
+        body: Option<String>,
+    },
+}
+
+impl From<ReleaseRepr> for ReleaseInfo {
+    fn from(repr: ReleaseRepr) -> Self {
+        match repr {
+            ReleaseRepr::Tag(tag) => ReleaseInfo::from_tag(tag),
+            ReleaseRepr::Full {
+                tag,
+                published_at,
+                body,
+            } => ReleaseInfo {
+                tag,
+                published_at,
+                body,
+            },
+        }
+    }
+}
+
+impl From<octocrab::models::repos::Release> for ReleaseInfo {
+    fn from(release: octocrab::models::repos::Release) -> Self {
+        ReleaseInfo {
+            tag: release.tag_name,
+            published_at: release.published_at,
+            body: release.body.filter(|body| !body.is_empty()),
+        }
+    }
+}
+
+impl ReleaseInfo {
+    /// A release known only by its tag, e.g. a bare git tag.
+    pub fn from_tag(tag: impl Into<String>) -> Self {
+        ReleaseInfo {
+            tag: tag.into(),
+            published_at: None,
+            body: None,
+        }
+    }
+}
+
+impl Display for ReleaseInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.tag)
+    }
+}
+
+/// A file attached to a GitHub release.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AssetInfo {
+    pub name: String,
+    pub download_url: String,
+    /// Only computed by `cmk add --checksum`, which downloads the asset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+}
+
+impl From<&octocrab::models::repos::Asset> for AssetInfo {
+    fn from(asset: &octocrab::models::repos::Asset) -> Self {
+        AssetInfo {
+            name: asset.name.clone(),
+            download_url: asset.browser_download_url.to_string(),
+            sha256: None,
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct PackageIndex {
-    pub aliases: HashMap<String, Package>,
-    pub releases: HashMap<String, String>,
+    pub aliases: BTreeMap<String, Package>,
+    pub releases: BTreeMap<String, ReleaseInfo>,
+    /// The release each package had before its last `update`, for `rollback`.
+    #[serde(default)]
+    pub previous_releases: BTreeMap<String, ReleaseInfo>,
+    /// Assets of each package's current release. Packages tracked by tag
+    /// only have none.
+    #[serde(default)]
+    pub release_assets: BTreeMap<String, Vec<AssetInfo>>,
 }
 
 impl PackageIndex {
     pub fn load_or_create(path: &PathBuf) -> Result<Self> {
         if !path.try_exists()? {
             let index = Self {
-                aliases: HashMap::new(),
-                releases: HashMap::new(),
+                aliases: BTreeMap::new(),
+                releases: BTreeMap::new(),
+                previous_releases: BTreeMap::new(),
+                release_assets: BTreeMap::new(),
             };
             index.save(path)?;
             return Ok(index);
@@ -59,8 +170,10 @@ impl PackageIndex {
         Ok(index)
     }
 
+    /// Written pretty-printed with sorted keys so a checked-in pkg.json diffs
+    /// cleanly.
     pub fn save(&self, path: &PathBuf) -> Result<()> {
-        let content = serde_json::to_string(self)?;
+        let content = serde_json::to_string_pretty(self)?;
         let parent = path
             .parent()
             .with_context(|| "Failed to get parent directory")?;
@@ -81,75 +194,390 @@ impl PackageIndex {
         Ok(pkg_name)
     }
 
-    pub fn get_release(&self, name: &str) -> Result<&str> {
+    /// Re-key alias `old` as `new`. The package it points to is unchanged.
+    pub fn rename_alias(&mut self, old: &str, new: &str) -> Result<&Package> {
+        if self.aliases.contains_key(new) {
+            return Err(anyhow!("Package alias {new} already exists"));
+        }
+        let pkg = self
+            .aliases
+            .remove(old)
+            .with_context(|| format!("Package alias {old} not found"))?;
+        Ok(self.aliases.entry(new.to_string()).or_insert(pkg))
+    }
+
+    pub fn get_release(&self, name: &str) -> Result<&ReleaseInfo> {
         let name = self.get_pkg_name(name)?;
-        let release = self.releases.get(&name).map(|s| s.as_str());
+        let release = self.releases.get(&name);
         release.with_context(|| format!("Release {name} not found"))
     }
 
-    pub async fn add_repo(&mut self, owner: &str, repo: &str) -> Result<()> {
-        let octocrab = octocrab::instance();
-        let release = octocrab.repos(owner, repo).releases().get_latest().await?;
+    /// Download URL of the current release's asset `asset_name`.
+    pub fn get_asset_url(&self, name: &str, asset_name: &str) -> Result<&str> {
+        let pkg_name = self.get_pkg_name(name)?;
+        let assets = self
+            .release_assets
+            .get(&pkg_name)
+            .with_context(|| format!("No release assets recorded for {pkg_name}"))?;
+        assets
+            .iter()
+            .find(|asset| asset.name == asset_name)
+            .map(|asset| asset.download_url.as_str())
+            .with_context(|| format!("{pkg_name} has no release asset {asset_name}"))
+    }
+
+    /// Record `assets` for `pkg_name`, keeping checksums already known for
+    /// the same download URL.
+    fn set_assets(&mut self, pkg_name: &str, mut assets: Vec<AssetInfo>) {
+        let known = self.release_assets.remove(pkg_name).unwrap_or_default();
+        for asset in &mut assets {
+            if asset.sha256.is_none() {
+                asset.sha256 = known
+                    .iter()
+                    .find(|k| k.download_url == asset.download_url)
+                    .and_then(|k| k.sha256.clone());
+            }
+        }
+        if !assets.is_empty() {
+            self.release_assets.insert(pkg_name.to_string(), assets);
+        }
+    }
+
+    /// Set the release of the package aliased `name` to `version` as CPM
+    /// reports it. Aliases and repo names match case-insensitively, and a
+    /// `v` prefix is kept when the tracked tag has one. Returns the package
+    /// name, or `None` when nothing matches.
+    pub fn pin(&mut self, name: &str, version: &str) -> Option<String> {
+        let pkg_name = self
+            .aliases
+            .iter()
+            .find(|(alias, pkg)| {
+                alias.eq_ignore_ascii_case(name) || pkg.repo.eq_ignore_ascii_case(name)
+            })
+            .map(|(_, pkg)| pkg.to_string())?;
+        let current = self.releases.get(&pkg_name);
+        let tag = match current {
+            Some(c) if c.tag.starts_with('v') && !version.starts_with('v') => format!("v{version}"),
+            _ => version.to_string(),
+        };
+        if current.is_some_and(|c| c.tag == tag) {
+            return Some(pkg_name);
+        }
+        let release = ReleaseInfo::from_tag(tag);
+        match self.releases.insert(pkg_name.clone(), release.clone()) {
+            Some(previous) => {
+                println!(
+                    "{pkg_name}: {previous} {} {}",
+                    style::arrow(),
+                    style::green(&release)
+                );
+                self.previous_releases.insert(pkg_name.clone(), previous);
+            }
+            None => println!("{pkg_name}: {release}"),
+        }
+        Some(pkg_name)
+    }
+
+    /// The package `alias` points at, if that is not `pkg_name`.
+    pub fn alias_conflict(&self, alias: &str, pkg_name: &str) -> Option<&Package> {
+        self.aliases
+            .get(alias)
+            .filter(|existing| existing.to_string() != pkg_name)
+    }
+
+    /// Track the latest release of `owner/repo` under `alias` (default:
+    /// `repo`). An alias already pointing at another package is only
+    /// replaced with `force`. With `allow_prerelease`, pre-releases count as
+    /// the latest release, now and on every `update`. The release's assets
+    /// are recorded too; `checksum` downloads each one to store its SHA256.
+    pub async fn add_repo(
+        &mut self,
+        owner: &str,
+        repo: &str,
+        alias: Option<&str>,
+        allow_prerelease: bool,
+        force: bool,
+        checksum: bool,
+    ) -> Result<()> {
         let pkg_name = format!("{owner}/{repo}");
-        self.aliases.insert(
-            repo.to_string(),
-            Package {
-                owner: owner.to_string(),
-                repo: repo.to_string(),
-            },
-        );
-        println!("{}: {}", pkg_name, release.tag_name);
-        self.releases.insert(pkg_name, release.tag_name);
+        let alias = alias.unwrap_or(repo);
+        if let Some(existing) = self.alias_conflict(alias, &pkg_name) {
+            if !force {
+                return Err(anyhow!(
+                    "Package alias {alias} already points at {existing}; pass --force to replace it"
+                ));
+            }
+            println!(
+                "{alias}: {existing} {} {}",
+                style::arrow(),
+                style::green(&pkg_name)
+            );
+        }
+        let package = Package {
+            owner: owner.to_string(),
+            repo: repo.to_string(),
+            allow_prerelease,
+        };
+        let octocrab = octocrab::instance();
+        let (mut assets, release) = match latest_release(&octocrab, &package).await? {
+            Some(release) => (
+                release.assets.iter().map(AssetInfo::from).collect(),
+                ReleaseInfo::from(release),
+            ),
+            // No published release yet; track the most recent tag instead.
+            None => octocrab
+                .repos(owner, repo)
+                .list_tags()
+                .per_page(1)
+                .send()
+                .await
+                .with_context(|| format!("Failed to list tags of {pkg_name}"))?
+                .items
+                .into_iter()
+                .next()
+                .map(|tag| ReleaseInfo::from_tag(tag.name))
+                .map(|release| (Vec::new(), release))
+                .with_context(|| format!("{pkg_name} has no releases or tags"))?,
+        };
+        if checksum {
+            let digests = join_all(assets.iter().map(|asset| sha256_of(&asset.download_url))).await;
+            for (asset, digest) in assets.iter_mut().zip(digests) {
+                asset.sha256 =
+                    Some(digest.with_context(|| format!("Failed to hash {}", asset.name))?);
+            }
+        }
+        self.aliases.insert(alias.to_string(), package);
+        println!("{pkg_name}: {release}");
+        self.set_assets(&pkg_name, assets);
+        self.releases.insert(pkg_name, release);
         Ok(())
     }
 
-    pub async fn update(&mut self) -> Result<()> {
+    /// Query the latest release of every tracked package. A package whose
+    /// query fails is reported and skipped, and the rest are still updated;
+    /// with `fail_fast`, any failure makes the whole update an error.
+    pub async fn update(&mut self, fail_fast: bool) -> Result<()> {
         let octocrab = octocrab::instance();
 
+        let mut names = Vec::new();
         let mut futures = Vec::new();
         for pkg in self.aliases.values() {
             let octocrab = octocrab.clone();
             let pkg = pkg.clone();
+            names.push(pkg.to_string());
 
-            let future: JoinHandle<Result<(String, String)>> = tokio::spawn(async move {
-                let release = octocrab
-                    .repos(&pkg.owner, &pkg.repo)
-                    .releases()
-                    .get_latest()
-                    .await?;
-                Ok((pkg.to_string(), release.tag_name))
+            let future: JoinHandle<Result<UpdateOutcome>> = tokio::spawn(async move {
+                Ok(match latest_release(&octocrab, &pkg).await? {
+                    Some(release) => {
+                        let assets = release.assets.iter().map(AssetInfo::from).collect();
+                        UpdateOutcome::Release(release.into(), assets)
+                    }
+                    None => UpdateOutcome::NoReleases,
+                })
             });
 
             futures.push(future);
         }
 
-        for result in join_all(futures).await {
-            match result? {
-                Ok((pkg_name, tag_name)) => {
-                    let existing = self
-                        .releases
-                        .get(&pkg_name)
-                        .with_context(|| format!("Package {pkg_name} not found"))?;
-                    if existing == &tag_name {
-                        continue;
-                    }
-                    println!("{pkg_name}: {existing} -> {tag_name}");
-                    self.releases.insert(pkg_name, tag_name);
+        let mut failed: Vec<(String, String)> = Vec::new();
+        for (pkg_name, result) in names.into_iter().zip(join_all(futures).await) {
+            match result {
+                Ok(Ok(outcome)) => self.apply_update(pkg_name, outcome)?,
+                Ok(Err(e)) => {
+                    eprintln!("Failed to update package {pkg_name}: {e}");
+                    failed.push((pkg_name, e.to_string()));
                 }
                 Err(e) => {
-                    eprintln!("Failed to update package: {e}");
+                    eprintln!("Update task for package {pkg_name} failed: {e}");
+                    failed.push((pkg_name, e.to_string()));
                 }
             }
         }
 
+        if failed.is_empty() {
+            return Ok(());
+        }
+        eprintln!("{} package(s) could not be updated:", failed.len());
+        for (pkg_name, error) in &failed {
+            eprintln!("  {pkg_name}: {error}");
+        }
+        if fail_fast {
+            return Err(anyhow!("{} package(s) failed to update", failed.len()));
+        }
+        Ok(())
+    }
+
+    fn apply_update(&mut self, pkg_name: String, outcome: UpdateOutcome) -> Result<()> {
+        match outcome {
+            UpdateOutcome::Release(release, assets) => {
+                let existing = self
+                    .releases
+                    .get(&pkg_name)
+                    .with_context(|| format!("Package {pkg_name} not found"))?
+                    .clone();
+                self.set_assets(&pkg_name, assets);
+                if existing.tag == release.tag {
+                    // Same release; pick up metadata an old index lacked.
+                    self.releases.insert(pkg_name, release);
+                    return Ok(());
+                }
+                println!(
+                    "{pkg_name}: {existing} {} {}",
+                    style::arrow(),
+                    style::green(&release)
+                );
+                self.previous_releases.insert(pkg_name.clone(), existing);
+                self.releases.insert(pkg_name, release);
+            }
+            UpdateOutcome::NoReleases => {
+                println!("{pkg_name}: no releases published, skipped");
+            }
+        }
+        Ok(())
+    }
+}
+
+/// What querying one package's latest release produced during `update`.
+enum UpdateOutcome {
+    Release(ReleaseInfo, Vec<AssetInfo>),
+    /// The repo exists but has never published a release (GitHub answers 404).
+    NoReleases,
+}
+
+/// Tag of the latest stable release of `owner/repo`, looked up without
+/// touching the index. `None` when the repo has no releases.
+pub async fn query_latest_tag(owner: &str, repo: &str) -> Result<Option<String>> {
+    let package = Package {
+        owner: owner.to_string(),
+        repo: repo.to_string(),
+        allow_prerelease: false,
+    };
+    let release = latest_release(&octocrab::instance(), &package)
+        .await
+        .with_context(|| format!("Failed to query the latest release of {package}"))?;
+    Ok(release.map(|release| release.tag_name))
+}
+
+/// `pkg`'s newest release, or `None` when it has never published one.
+/// `get_latest` skips pre-releases, so packages that allow them take the
+/// first non-draft entry of the (newest-first) release list instead.
+async fn latest_release(
+    octocrab: &octocrab::Octocrab,
+    pkg: &Package,
+) -> octocrab::Result<Option<octocrab::models::repos::Release>> {
+    let releases = octocrab.repos(&pkg.owner, &pkg.repo).releases();
+    if pkg.allow_prerelease {
+        let page = releases.list().per_page(10).send().await?;
+        return Ok(page.items.into_iter().find(|release| !release.draft));
+    }
+    match releases.get_latest().await {
+        Ok(release) => Ok(Some(release)),
+        Err(e) if is_not_found(&e) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+fn is_not_found(e: &octocrab::Error) -> bool {
+    matches!(e, octocrab::Error::GitHub { source, .. } if source.status_code.as_u16() == 404)
+}
+
+/// Which side wins when [`PackageIndex::merge`] finds an alias or release
+/// that differs between the two indexes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MergePreference {
+    Theirs,
+    Mine,
+}
+
+impl PackageIndex {
+    /// Merge `other`'s aliases and releases into `self`. Without `prefer`,
+    /// any conflicting entry is an error and nothing is merged.
+    pub fn merge(&mut self, other: PackageIndex, prefer: Option<MergePreference>) -> Result<()> {
+        let mut conflicts = Vec::new();
+        for (alias, theirs) in &other.aliases {
+            if let Some(mine) = self.aliases.get(alias)
+                && mine.to_string() != theirs.to_string()
+            {
+                conflicts.push(format!("alias {alias}: {mine} (mine) vs {theirs} (theirs)"));
+            }
+        }
+        for (name, theirs) in &other.releases {
+            if let Some(mine) = self.releases.get(name)
+                && mine.tag != theirs.tag
+            {
+                conflicts.push(format!("{name}: {mine} (mine) vs {theirs} (theirs)"));
+            }
+        }
+        if !conflicts.is_empty() && prefer.is_none() {
+            conflicts.sort();
+            return Err(anyhow!(
+                "Conflicting entries (pass --prefer theirs|mine to resolve):\n  {}",
+                conflicts.join("\n  ")
+            ));
+        }
+
+        let theirs_wins = prefer == Some(MergePreference::Theirs);
+        merge_map(&mut self.aliases, other.aliases, theirs_wins);
+        merge_map(&mut self.releases, other.releases, theirs_wins);
+        merge_map(&mut self.release_assets, other.release_assets, theirs_wins);
+        Ok(())
+    }
+
+    /// Swap `releases[pkg]` with `previous_releases[pkg]`, so a second
+    /// rollback undoes the first. `None` rolls back every package that has a
+    /// previous release recorded.
+    pub fn rollback(&mut self, pkg: Option<&str>) -> Result<()> {
+        let names = match pkg {
+            Some(name) => vec![self.get_pkg_name(name)?],
+            None => self.previous_releases.keys().cloned().collect(),
+        };
+        if names.is_empty() {
+            println!("No previous releases recorded.");
+        }
+        for name in names {
+            let previous = self
+                .previous_releases
+                .remove(&name)
+                .with_context(|| format!("No previous release recorded for {name}"))?;
+            let current = self.releases.insert(name.clone(), previous.clone());
+            // Assets are only known for the release `update` last fetched.
+            self.release_assets.remove(&name);
+            println!(
+                "{name}: {} {} {}",
+                current.as_ref().map_or("(none)", |c| c.tag.as_str()),
+                style::arrow(),
+                style::green(&previous)
+            );
+            if let Some(current) = current {
+                self.previous_releases.insert(name, current);
+            }
+        }
         Ok(())
     }
 }
 
+fn merge_map<V>(mine: &mut BTreeMap<String, V>, theirs: BTreeMap<String, V>, theirs_wins: bool) {
+    for (key, value) in theirs {
+        match mine.entry(key) {
+            Entry::Occupied(mut e) if theirs_wins => {
+                e.insert(value);
+            }
+            Entry::Occupied(_) => {}
+            Entry::Vacant(e) => {
+                e.insert(value);
+            }
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct CpmInfo {
     pub version: String,
     pub sha256: String,
+    /// When this was queried from GitHub. Caches written before this was
+    /// recorded have none and count as stale.
+    #[serde(default)]
+    pub fetched_at: Option<DateTime<Utc>>,
 }
 
 impl CpmInfo {
@@ -166,6 +594,20 @@ impl CpmInfo {
         Ok(())
     }
 
+    /// Whether the cache is older than the [`CPM_TTL_ENV`] TTL.
+    pub fn is_stale(&self) -> bool {
+        let days = std::env::var(CPM_TTL_ENV)
+            .ok()
+            .and_then(|v| v.trim().parse::<i64>().ok())
+            .filter(|&days| days >= 0)
+            .unwrap_or(DEFAULT_CPM_TTL_DAYS);
+        self.is_stale_at(chrono::Duration::days(days), Utc::now())
+    }
+
+    fn is_stale_at(&self, ttl: chrono::Duration, now: DateTime<Utc>) -> bool {
+        self.fetched_at.is_none_or(|at| now - at >= ttl)
+    }
+
     pub async fn query_from_github() -> Result<Self> {
         let octocrab = octocrab::instance();
 
@@ -180,23 +622,216 @@ impl CpmInfo {
             .strip_prefix('v')
             .unwrap_or(&release.tag_name);
 
-        let asset = release
-            .assets
-            .first()
-            .with_context(|| "No assets found in release")?;
-
-        let content = reqwest::get(asset.browser_download_url.clone())
-            .await?
-            .bytes()
-            .await?;
-
-        let mut hasher = sha2::Sha256::new();
-        hasher.update(&content);
-        let sha256 = hasher.finalize();
+        let assets: Vec<AssetInfo> = release.assets.iter().map(AssetInfo::from).collect();
+        let asset = cpm_script_asset(&assets)
+            .with_context(|| format!("CPM.cmake release {}", release.tag_name))?;
 
         Ok(CpmInfo {
             version: tag.to_string(),
-            sha256: format!("{sha256:x}"),
+            sha256: sha256_of(&asset.download_url).await?,
+            fetched_at: Some(Utc::now()),
+        })
+    }
+}
+
+/// The `.cmake` script among a CPM.cmake release's assets, whatever their
+/// order.
+fn cpm_script_asset(assets: &[AssetInfo]) -> Result<&AssetInfo> {
+    assets
+        .iter()
+        .find(|asset| asset.name.ends_with(".cmake"))
+        .ok_or_else(|| {
+            let names: Vec<&str> = assets.iter().map(|asset| asset.name.as_str()).collect();
+            anyhow!(
+                "No .cmake asset found in release (assets: {})",
+                names.join(", ")
+            )
         })
+}
+
+/// Download `url` and return its SHA256 as lowercase hex.
+async fn sha256_of(url: &str) -> Result<String> {
+    let content = reqwest::get(url).await?.error_for_status()?.bytes().await?;
+    let mut hasher = sha2::Sha256::new();
+    hasher.update(&content);
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn index(aliases: &[(&str, &str)], releases: &[(&str, &str)]) -> PackageIndex {
+        PackageIndex {
+            aliases: aliases
+                .iter()
+                .map(|(alias, pkg)| {
+                    let (owner, repo) = pkg.split_once('/').unwrap();
+                    (
+                        alias.to_string(),
+                        Package {
+                            owner: owner.to_string(),
+                            repo: repo.to_string(),
+                            allow_prerelease: false,
+                        },
+                    )
+                })
+                .collect(),
+            releases: releases
+                .iter()
+                .map(|(k, v)| (k.to_string(), ReleaseInfo::from_tag(*v)))
+                .collect(),
+            previous_releases: BTreeMap::new(),
+            release_assets: BTreeMap::new(),
+        }
+    }
+
+    #[test]
+    fn update_skips_repo_without_releases() {
+        let mut index = index(&[("fmt", "fmtlib/fmt")], &[("fmtlib/fmt", "11.0.0")]);
+        index
+            .apply_update("fmtlib/fmt".to_string(), UpdateOutcome::NoReleases)
+            .unwrap();
+        assert_eq!(index.releases["fmtlib/fmt"].tag, "11.0.0");
+        assert!(index.previous_releases.is_empty());
+
+        index
+            .apply_update(
+                "fmtlib/fmt".to_string(),
+                UpdateOutcome::Release(ReleaseInfo::from_tag("12.1.0"), Vec::new()),
+            )
+            .unwrap();
+        assert_eq!(index.releases["fmtlib/fmt"].tag, "12.1.0");
+        assert_eq!(index.previous_releases["fmtlib/fmt"].tag, "11.0.0");
+    }
+
+    #[test]
+    fn merge_resolves_conflicts_by_preference() {
+        let mine = || index(&[("fmt", "fmtlib/fmt")], &[("fmtlib/fmt", "11.0.0")]);
+        let theirs = || {
+            index(
+                &[("fmt", "fmtlib/fmt"), ("json", "nlohmann/json")],
+                &[("fmtlib/fmt", "12.1.0"), ("nlohmann/json", "v3.12.0")],
+            )
+        };
+
+        assert!(mine().merge(theirs(), None).is_err());
+
+        let mut merged = mine();
+        merged.merge(theirs(), Some(MergePreference::Mine)).unwrap();
+        assert_eq!(merged.releases["fmtlib/fmt"].tag, "11.0.0");
+        assert_eq!(merged.releases["nlohmann/json"].tag, "v3.12.0");
+        assert_eq!(merged.aliases["json"].to_string(), "nlohmann/json");
+
+        let mut merged = mine();
+        merged
+            .merge(theirs(), Some(MergePreference::Theirs))
+            .unwrap();
+        assert_eq!(merged.releases["fmtlib/fmt"].tag, "12.1.0");
+    }
+
+    #[test]
+    fn pin_matches_aliases_case_insensitively() {
+        let mut index = index(
+            &[("fmt", "fmtlib/fmt"), ("json", "nlohmann/json")],
+            &[("fmtlib/fmt", "11.0.0"), ("nlohmann/json", "v3.11.0")],
+        );
+        assert_eq!(index.pin("FMT", "12.1.0").as_deref(), Some("fmtlib/fmt"));
+        assert_eq!(index.pin("nlohmann_json", "3.12.0"), None);
+        assert_eq!(
+            index.pin("JSON", "3.12.0").as_deref(),
+            Some("nlohmann/json")
+        );
+        assert_eq!(index.releases["fmtlib/fmt"].tag, "12.1.0");
+        assert_eq!(index.previous_releases["fmtlib/fmt"].tag, "11.0.0");
+        assert_eq!(index.releases["nlohmann/json"].tag, "v3.12.0");
+    }
+
+    #[test]
+    fn releases_load_from_tag_strings_and_objects() {
+        let json = r#"{
+            "aliases": {},
+            "releases": {
+                "fmtlib/fmt": "11.0.0",
+                "nlohmann/json": {"tag": "v3.12.0", "published_at": "2025-04-11T08:43:39Z"}
+            }
+        }"#;
+        let index: PackageIndex = serde_json::from_str(json).unwrap();
+        assert_eq!(
+            index.releases["fmtlib/fmt"],
+            ReleaseInfo::from_tag("11.0.0")
+        );
+        let json_release = &index.releases["nlohmann/json"];
+        assert_eq!(json_release.tag, "v3.12.0");
+        assert!(json_release.published_at.is_some());
+        assert_eq!(json_release.body, None);
+
+        let saved = serde_json::to_string(&index.releases["fmtlib/fmt"]).unwrap();
+        assert_eq!(saved, r#"{"tag":"11.0.0"}"#);
+    }
+
+    #[test]
+    fn cpm_script_asset_skips_non_cmake_assets() {
+        let asset = |name: &str| AssetInfo {
+            name: name.to_string(),
+            download_url: format!("https://example.com/v0.40.2/{name}"),
+            sha256: None,
+        };
+        let assets = vec![
+            asset("CPM.cmake.sha256"),
+            asset("get_cpm.tar.gz"),
+            asset("CPM.cmake"),
+        ];
+        assert_eq!(cpm_script_asset(&assets).unwrap().name, "CPM.cmake");
+
+        let err = cpm_script_asset(&assets[..2]).unwrap_err().to_string();
+        assert!(err.contains("No .cmake asset"), "{err}");
+        assert!(err.contains("get_cpm.tar.gz"), "{err}");
+        assert!(cpm_script_asset(&[]).is_err());
+    }
+
+    #[test]
+    fn asset_urls_resolve_and_keep_checksums_across_updates() {
+        let asset = |name: &str, sha256: Option<&str>| AssetInfo {
+            name: name.to_string(),
+            download_url: format!("https://example.com/v1/{name}"),
+            sha256: sha256.map(str::to_string),
+        };
+        let mut index = index(&[("tool", "acme/tool")], &[("acme/tool", "v1")]);
+        index.set_assets("acme/tool", vec![asset("tool.tar.gz", Some("abc"))]);
+        assert_eq!(
+            index.get_asset_url("tool", "tool.tar.gz").unwrap(),
+            "https://example.com/v1/tool.tar.gz"
+        );
+        assert!(index.get_asset_url("tool", "tool.zip").is_err());
+
+        index
+            .apply_update(
+                "acme/tool".to_string(),
+                UpdateOutcome::Release(
+                    ReleaseInfo::from_tag("v1"),
+                    vec![asset("tool.tar.gz", None), asset("tool.zip", None)],
+                ),
+            )
+            .unwrap();
+        let assets = &index.release_assets["acme/tool"];
+        assert_eq!(assets[0].sha256.as_deref(), Some("abc"));
+        assert_eq!(assets[1].sha256, None);
+    }
+
+    #[test]
+    fn cpm_info_goes_stale_after_ttl() {
+        let now = Utc::now();
+        let mut info = CpmInfo {
+            version: "0.40.0".to_string(),
+            sha256: String::new(),
+            fetched_at: None,
+        };
+        let week = chrono::Duration::days(7);
+        assert!(info.is_stale_at(week, now));
+        info.fetched_at = Some(now - chrono::Duration::days(2));
+        assert!(!info.is_stale_at(week, now));
+        assert!(info.is_stale_at(chrono::Duration::days(1), now));
+        assert!(info.is_stale_at(chrono::Duration::zero(), now));
     }
 }
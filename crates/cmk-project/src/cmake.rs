@@ -1,31 +1,220 @@
 use anyhow::{Context, Result, anyhow};
 use serde::{Deserialize, Serialize};
 use std::{
-    collections::HashMap,
+    collections::{BTreeMap, HashMap, VecDeque},
     path::{Path, PathBuf},
     process::Stdio,
+    sync::{Mutex, OnceLock},
+    time::Duration,
 };
 use tokio::process::Command;
 
-use cmk_config::{BuildConfig, BuildEnv};
-use cmk_core::process::{completing_read, wait_with_cancel};
+use cmk_config::{BuildConfig, BuildEnv, CmkConfig, Hook, HooksConfig, MANAGED_FLAG_VARS};
+use cmk_core::process::{
+    ChildFailed, TimedOut, completing_read, wait_foreground_timeout, wait_with_cancel,
+};
+
+use crate::{CmkError, ninja};
+
+/// Fail with a clear message when `git` itself can't be run; cmk relies on
+/// it to locate the project root.
+pub async fn require_git() -> Result<()> {
+    match Command::new("git").arg("--version").output().await {
+        Ok(output) if output.status.success() => Ok(()),
+        Ok(output) => Err(anyhow!("`git --version` failed ({})", output.status)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Err(CmkError::GitNotInstalled.into()),
+        Err(e) => Err(anyhow::Error::new(e).context("Failed to run git")),
+    }
+}
+
+/// Names the project root directly, skipping git discovery.
+pub const PROJECT_ROOT_ENV: &str = "CMK_ROOT";
 
+/// Set for `[[hooks.post_run]]` commands to the exit code of the target
+/// (128 + signal when killed by one, 124 on `--timeout`).
+pub const RUN_EXIT_CODE_ENV: &str = "CMK_RUN_EXIT_CODE";
+
+/// Names the build dir to use when a command isn't given `--build`.
+pub const BUILD_DIR_ENV: &str = "CMK_BUILD_DIR";
+
+static PROJECT_ROOT_OVERRIDE: OnceLock<PathBuf> = OnceLock::new();
+
+/// Use `root` as the project root for the rest of the process (`cmk
+/// --root`). Takes precedence over `CMK_ROOT`.
+pub fn set_project_root(root: PathBuf) {
+    let _ = PROJECT_ROOT_OVERRIDE.set(root);
+}
+
+/// The `--root` / `CMK_ROOT` project root, made absolute.
+fn project_root_override() -> Result<Option<PathBuf>> {
+    let Some(root) = PROJECT_ROOT_OVERRIDE.get().cloned().or_else(|| {
+        std::env::var_os(PROJECT_ROOT_ENV)
+            .filter(|v| !v.is_empty())
+            .map(PathBuf::from)
+    }) else {
+        return Ok(None);
+    };
+    let root = std::fs::canonicalize(&root)
+        .with_context(|| format!("Project root {} does not exist", root.display()))?;
+    if !root.is_dir() {
+        return Err(anyhow!(
+            "Project root {} is not a directory",
+            root.display()
+        ));
+    }
+    Ok(Some(root))
+}
+
+/// The `--root` / `CMK_ROOT` override if set, else the top of the git
+/// repository (the superproject's, inside a submodule).
 pub async fn get_project_root() -> Result<PathBuf> {
+    if let Some(root) = project_root_override()? {
+        return Ok(root);
+    }
+    require_git().await?;
+    // One flag per call: with both, the paths would have to be told apart by
+    // splitting on newlines, which a path may itself contain.
+    if let Some(superproject) = git_rev_parse_path("--show-superproject-working-tree").await? {
+        return Ok(superproject);
+    }
+    git_rev_parse_path("--show-toplevel")
+        .await?
+        .with_context(|| "git rev-parse printed no repository root")
+}
+
+/// The path `git rev-parse <flag>` prints, or `None` when it prints nothing.
+async fn git_rev_parse_path(flag: &str) -> Result<Option<PathBuf>> {
     let output = Command::new("git")
-        .args([
-            "rev-parse",
-            "--show-superproject-working-tree",
-            "--show-toplevel",
-        ])
+        .args(["rev-parse", flag])
         .env("GIT_DISCOVERY_ACROSS_FILESYSTEM", "1")
         .output()
         .await?;
-    let output = String::from_utf8(output.stdout)?;
-    let head = output
-        .split("\n")
-        .next()
-        .with_context(|| "No git repository found")?;
-    Ok(PathBuf::from(head))
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        if stderr.contains("not a git repository") {
+            return Err(CmkError::NoGitRepository.into());
+        }
+        return Err(anyhow!(
+            "git rev-parse failed ({}): {}",
+            output.status,
+            stderr.trim()
+        ));
+    }
+    Ok(parse_rev_parse_path(&String::from_utf8(output.stdout)?))
+}
+
+/// A single path printed by `git rev-parse`. Only the line terminator git
+/// adds is removed: spaces and newlines inside or at the end of the path are
+/// part of it.
+fn parse_rev_parse_path(stdout: &str) -> Option<PathBuf> {
+    let path = stdout.strip_suffix('\n').unwrap_or(stdout);
+    let path = path.strip_suffix('\r').unwrap_or(path);
+    (!path.is_empty()).then(|| PathBuf::from(path))
+}
+
+/// Error for a `cmake` command that could not be started, reporting a
+/// missing binary as [`CmkError::CmakeNotInstalled`].
+fn cmake_spawn_error(e: std::io::Error) -> anyhow::Error {
+    if e.kind() == std::io::ErrorKind::NotFound {
+        CmkError::CmakeNotInstalled.into()
+    } else {
+        anyhow::Error::new(e).context("Failed to run cmake")
+    }
+}
+
+/// Whether the build dir was configured with a Ninja generator (including
+/// Ninja Multi-Config). Assumed when the cache records no generator.
+pub fn is_ninja_build_dir(build_dir: &Path) -> bool {
+    read_cache_variable(build_dir, "CMAKE_GENERATOR")
+        .ok()
+        .flatten()
+        .is_none_or(|generator| generator.starts_with("Ninja"))
+}
+
+/// Files whose change makes the generator rerun CMake in `build_dir`: the
+/// `RERUN_CMAKE` inputs of `build.ninja`, or `CMAKE_MAKEFILE_DEPENDS` in
+/// `CMakeFiles/Makefile.cmake` for Makefiles. Empty if neither is readable.
+fn regeneration_inputs(build_dir: &Path) -> Vec<PathBuf> {
+    if is_ninja_build_dir(build_dir) {
+        return ninja::read_regeneration_inputs(build_dir).unwrap_or_default();
+    }
+    std::fs::read_to_string(build_dir.join("CMakeFiles/Makefile.cmake"))
+        .map(|content| {
+            parse_makefile_depends(&content)
+                .into_iter()
+                .map(|p| build_dir.join(p))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// The quoted paths of `set(CMAKE_MAKEFILE_DEPENDS ...)` in `Makefile.cmake`.
+fn parse_makefile_depends(content: &str) -> Vec<String> {
+    let Some(start) = content.find("set(CMAKE_MAKEFILE_DEPENDS") else {
+        return Vec::new();
+    };
+    let body = &content[start..];
+    let body = &body[..body.find(')').unwrap_or(body.len())];
+    body.split('"')
+        .skip(1)
+        .step_by(2)
+        .map(str::to_string)
+        .collect()
+}
+
+/// Parse `CMakeCache.txt` in `build_dir` into `NAME -> VALUE`. Entries are
+/// `NAME:TYPE=VALUE`; comments (`#`, `//`) and blank lines are skipped.
+pub fn read_all_cache_variables(build_dir: &Path) -> Result<HashMap<String, String>> {
+    let path = build_dir.join("CMakeCache.txt");
+    let content = std::fs::read_to_string(&path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    let mut vars = HashMap::new();
+    for line in content.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || line.starts_with("//") {
+            continue;
+        }
+        let Some((key, value)) = line.split_once('=') else {
+            continue;
+        };
+        let name = key.split_once(':').map_or(key, |(name, _)| name);
+        vars.insert(name.to_string(), value.to_string());
+    }
+    Ok(vars)
+}
+
+pub fn read_cache_variable(build_dir: &Path, name: &str) -> Result<Option<String>> {
+    Ok(read_all_cache_variables(build_dir)?.remove(name))
+}
+
+/// CMake's notion of a true constant (`ON`, `YES`, `TRUE`, `Y`, non-zero).
+pub fn is_cmake_true(value: &str) -> bool {
+    let v = value.trim().to_ascii_uppercase();
+    matches!(v.as_str(), "ON" | "YES" | "TRUE" | "Y") || v.parse::<f64>().is_ok_and(|n| n != 0.0)
+}
+
+/// Matcher for benchmark executable names given as glob `patterns`, e.g.
+/// `bench_*` or `*_bench`.
+fn benchmark_matcher(patterns: &[String]) -> Result<globset::GlobSet> {
+    let mut builder = globset::GlobSetBuilder::new();
+    for pattern in patterns {
+        builder.add(
+            globset::Glob::new(pattern)
+                .with_context(|| format!("Invalid benchmark pattern {pattern}"))?,
+        );
+    }
+    Ok(builder.build()?)
+}
+
+/// The first of `candidates` naming one of `build_dirs`.
+fn preferred_build_dir<'a>(
+    build_dirs: &'a HashMap<String, PathBuf>,
+    candidates: &[Option<&str>],
+) -> Option<&'a PathBuf> {
+    candidates
+        .iter()
+        .flatten()
+        .find_map(|name| build_dirs.get(*name))
 }
 
 pub struct CMakeProject {
@@ -33,72 +222,152 @@ pub struct CMakeProject {
     pub build_dirs: HashMap<String, PathBuf>,
     pub env_config: BuildEnv,
     pub build_config: BuildConfig,
+    pub hooks: HooksConfig,
+    target_cache: Mutex<TargetCache>,
+}
+
+/// File API targets already read, per build dir, so commands that look at
+/// the targets several times parse the reply directory once. Dropped for a
+/// build dir when it is reconfigured. A wrapper that drives several commands
+/// can hand it from one [`CMakeProject`] to the next with
+/// [`CMakeProject::into_cache`] and [`CMakeProject::with_cache`].
+#[derive(Debug, Default, Clone)]
+pub struct TargetCache {
+    /// Keyed by build dir path.
+    pub targets: HashMap<String, Vec<Target>>,
+    /// Whether any build dir's targets have been read.
+    pub populated: bool,
 }
 
+impl TargetCache {
+    fn key(build_dir: &Path) -> String {
+        build_dir.to_string_lossy().into_owned()
+    }
+
+    fn get(&self, build_dir: &Path) -> Option<&Vec<Target>> {
+        self.targets.get(&Self::key(build_dir))
+    }
+
+    fn insert(&mut self, build_dir: &Path, targets: Vec<Target>) {
+        self.targets.insert(Self::key(build_dir), targets);
+        self.populated = true;
+    }
+
+    fn invalidate(&mut self, build_dir: &Path) {
+        self.targets.remove(&Self::key(build_dir));
+    }
+}
+
+/// Source extensions `translation_units` keeps by default: C and C++.
+pub const DEFAULT_TU_EXTENSIONS: &[&str] = &["c", "cc", "cpp", "cxx", "c++"];
+
+/// Directory names that never hold a build tree; the build-dir search does
+/// not descend into them.
+const SKIPPED_DIRS: &[&str] = &[".git", "src", "include", "docs"];
+
+/// File inside a build dir recording the target last run or built there.
+const LAST_TARGET_FILE: &str = ".cmk_last_target";
+
 impl CMakeProject {
+    /// Open the project containing the current directory. Build dirs are
+    /// searched up to `CMK_MAX_DEPTH` levels below the project root
+    /// (default 3).
     pub async fn new() -> Result<Self> {
         let max_depth = std::env::var("CMK_MAX_DEPTH")
             .ok()
             .and_then(|s| s.parse::<usize>().ok())
-            .unwrap_or(2);
+            .unwrap_or(3);
         Self::new_with_max_depth(max_depth).await
     }
 
-    async fn new_with_max_depth(max_depth: usize) -> Result<Self> {
+    pub async fn new_with_max_depth(max_depth: usize) -> Result<Self> {
         let project_root = get_project_root().await?;
-        let mut build_dirs = HashMap::new();
-
-        Self::collect_build_dirs(&project_root, &project_root, &mut build_dirs, 1, max_depth)?;
+        let build_dirs = Self::collect_build_dirs(&project_root, max_depth)?;
 
         if build_dirs.is_empty() {
-            return Err(anyhow!("No CMake build directories found"));
+            return Err(CmkError::NoBuildDirectory(project_root).into());
         }
 
         let env_config = BuildEnv::load(&project_root)?;
         let build_config = BuildConfig::load(&project_root)?;
+        let hooks = CmkConfig::load(&project_root)?.hooks;
 
         Ok(Self {
             project_root,
             build_dirs,
             env_config,
             build_config,
+            hooks,
+            target_cache: Mutex::default(),
         })
     }
 
+    /// [`Self::new`], reusing targets an earlier `CMakeProject` already
+    /// read, e.g. one taken with [`Self::into_cache`].
+    pub async fn with_cache(cache: TargetCache) -> Result<Self> {
+        Ok(Self {
+            target_cache: Mutex::new(cache),
+            ..Self::new().await?
+        })
+    }
+
+    /// The targets read so far, for [`Self::with_cache`].
+    pub fn into_cache(self) -> TargetCache {
+        self.target_cache
+            .into_inner()
+            .unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn target_cache(&self) -> std::sync::MutexGuard<'_, TargetCache> {
+        self.target_cache.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Breadth-first search for directories containing `CMakeCache.txt`, at
+    /// most `max_depth` levels below `project_root`. Found build dirs are not
+    /// descended into, so `_deps/*-subbuild` caches are not picked up.
     fn collect_build_dirs(
         project_root: &Path,
-        current_dir: &Path,
-        build_dirs: &mut HashMap<String, PathBuf>,
-        current_depth: usize,
         max_depth: usize,
-    ) -> Result<()> {
-        for entry in std::fs::read_dir(current_dir)? {
-            let entry = entry?;
-            if entry.file_type()?.is_dir() {
-                let path = entry.path();
-
-                if path.join("CMakeCache.txt").try_exists()? {
-                    let relative_path = path
-                        .strip_prefix(project_root)
-                        .unwrap()
-                        .to_string_lossy()
-                        .to_string();
-                    build_dirs.insert(relative_path, path.clone());
+    ) -> Result<HashMap<String, PathBuf>> {
+        let mut build_dirs = HashMap::new();
+        let mut queue = VecDeque::from([(project_root.to_path_buf(), 0)]);
+        while let Some((dir, depth)) = queue.pop_front() {
+            if depth >= max_depth {
+                continue;
+            }
+            for entry in std::fs::read_dir(&dir)? {
+                let entry = entry?;
+                let file_type = entry.file_type()?;
+                if !file_type.is_dir() && !file_type.is_symlink() {
+                    continue;
                 }
-
-                if current_depth < max_depth {
-                    Self::collect_build_dirs(
-                        project_root,
-                        &path,
-                        build_dirs,
-                        current_depth + 1,
-                        max_depth,
-                    )?;
+                if SKIPPED_DIRS.contains(&entry.file_name().to_string_lossy().as_ref()) {
+                    continue;
+                }
+                let path = entry.path();
+                let relative_path = path
+                    .strip_prefix(project_root)
+                    .unwrap()
+                    .to_string_lossy()
+                    .to_string();
+                if file_type.is_symlink() {
+                    // A link such as `build -> /tmp/mybuild` counts when its
+                    // target is a build dir; links are never descended into,
+                    // which rules out cycles.
+                    if let Ok(target) = std::fs::canonicalize(&path)
+                        && target.join("CMakeCache.txt").try_exists()?
+                    {
+                        build_dirs.insert(relative_path, target);
+                    }
+                } else if path.join("CMakeCache.txt").try_exists()? {
+                    build_dirs.insert(relative_path, path);
+                } else {
+                    queue.push_back((path, depth + 1));
                 }
             }
         }
 
-        Ok(())
+        Ok(build_dirs)
     }
 
     pub fn get_build_dir(&self, build_dir_name: &str) -> Result<&PathBuf> {
@@ -123,22 +392,21 @@ impl CMakeProject {
     }
 
     pub async fn get_build_dir_from_input(&self) -> Result<&PathBuf> {
-        if self.build_dirs.len() == 1 {
+        if let Some(name) = std::env::var(BUILD_DIR_ENV).ok().filter(|v| !v.is_empty()) {
+            self.get_build_dir(&name)
+                .with_context(|| format!("{BUILD_DIR_ENV} is set to '{name}'"))
+        } else if self.build_dirs.len() == 1 {
             self.build_dirs
                 .values()
                 .next()
                 .with_context(|| "No build directories available")
         } else if let Some(p) = self.detect_pwd() {
             Ok(p)
-        } else if let Some(default) = &self.build_config.default {
-            self.build_dirs.get(default).with_context(|| {
-                format!(
-                    "Configured default build dir '{default}' not found. Known: {:?}",
-                    self.list_build_dirs()
-                )
-            })
+        } else if let Some(p) = self.default_build_dir() {
+            Ok(p)
         } else {
-            let res = completing_read(&self.list_build_dirs()).await?;
+            let res =
+                completing_read(&self.list_build_dirs(), Some(&self.build_dir_previews())).await?;
             if res.is_empty() {
                 return Err(anyhow!("No build directory selected"));
             }
@@ -146,8 +414,37 @@ impl CMakeProject {
         }
     }
 
+    /// The build dir named by `[build] default` in `.cmk.toml`, else by
+    /// `default_build_dir` in the user config, whichever exists. A missing
+    /// `[build] default` is warned about; the user config's default is
+    /// shared by every project, so it being absent is not.
+    fn default_build_dir(&self) -> Option<&PathBuf> {
+        if let Some(default) = &self.build_config.default
+            && !self.build_dirs.contains_key(default)
+        {
+            eprintln!(
+                "warning: [build] default '{default}' not found. Known: {:?}",
+                self.list_build_dirs()
+            );
+        }
+        let global = match cmk_core::Store::config_path()
+            .and_then(|path| cmk_core::Config::load_or_default(&path))
+        {
+            Ok(config) => config.default_build_dir,
+            Err(e) => {
+                eprintln!("warning: ignoring user config: {e}");
+                None
+            }
+        };
+        preferred_build_dir(
+            &self.build_dirs,
+            &[self.build_config.default.as_deref(), global.as_deref()],
+        )
+    }
+
     /// Resolve a build dir given an optional explicit name. When `None`,
-    /// follows the cascade: single → PWD → configured default → fzf prompt.
+    /// follows the cascade: `CMK_BUILD_DIR` → single → PWD → `[build]
+    /// default` → user config `default_build_dir` → fzf prompt.
     pub async fn resolve_build_dir(&self, name: Option<&str>) -> Result<&PathBuf> {
         match name {
             Some(n) => self.get_build_dir(n),
@@ -155,10 +452,105 @@ impl CMakeProject {
         }
     }
 
+    /// Like [`Self::resolve_build_dir`], but returns the build dir's name so
+    /// later calls can reuse it without prompting again.
+    pub async fn resolve_build_dir_name(&self, name: Option<&str>) -> Result<String> {
+        if let Some(name) = name {
+            self.get_build_dir(name)?;
+            return Ok(name.to_string());
+        }
+        let path = self.get_build_dir_from_input().await?;
+        self.build_dirs
+            .iter()
+            .find(|(_, p)| p == &path)
+            .map(|(key, _)| key.clone())
+            .with_context(|| format!("Unknown build directory {}", path.display()))
+    }
+
+    /// The target last run or built in the named build dir, if recorded.
+    pub fn last_target(&self, build_dir_name: &str) -> Option<String> {
+        let path = self
+            .get_build_dir(build_dir_name)
+            .ok()?
+            .join(LAST_TARGET_FILE);
+        let target = std::fs::read_to_string(path).ok()?;
+        let target = target.trim();
+        (!target.is_empty()).then(|| target.to_string())
+    }
+
+    /// Record `target` as the last one run or built in the named build dir.
+    pub fn remember_target(&self, build_dir_name: &str, target: &str) -> Result<()> {
+        let path = self.get_build_dir(build_dir_name)?.join(LAST_TARGET_FILE);
+        std::fs::write(&path, format!("{target}\n"))
+            .with_context(|| format!("Failed to write {}", path.display()))
+    }
+
     pub fn list_build_dirs(&self) -> Vec<String> {
         self.build_dirs.keys().cloned().collect()
     }
 
+    /// Generator, build type and compiler of every build dir, read from its
+    /// `CMakeCache.txt`, sorted by name. Fields the cache doesn't set (or a
+    /// cache that can't be read) come back as `None`.
+    pub fn build_dir_info(&self) -> Vec<BuildDirInfo> {
+        let mut infos: Vec<BuildDirInfo> = self
+            .build_dirs
+            .iter()
+            .map(|(name, path)| {
+                let cache = read_all_cache_variables(path).unwrap_or_default();
+                let get = |key: &str| cache.get(key).filter(|v| !v.is_empty()).cloned();
+                BuildDirInfo {
+                    name: name.clone(),
+                    path: path.clone(),
+                    generator: get("CMAKE_GENERATOR"),
+                    build_type: get("CMAKE_BUILD_TYPE"),
+                    compiler: get("CMAKE_CXX_COMPILER").or_else(|| get("CMAKE_C_COMPILER")),
+                }
+            })
+            .collect();
+        infos.sort_by(|a, b| a.name.cmp(&b.name));
+        infos
+    }
+
+    /// The build dir configured with `build_type` (e.g. `Release`), for
+    /// `--release`/`--debug`. When several are, the one holding the working
+    /// directory wins; otherwise the choice is left to `--build`.
+    pub fn build_dir_for_type(&self, build_type: &str) -> Result<String> {
+        let matches: Vec<String> = self
+            .build_dir_info()
+            .into_iter()
+            .filter(|info| {
+                info.build_type
+                    .as_deref()
+                    .is_some_and(|ty| ty.eq_ignore_ascii_case(build_type))
+            })
+            .map(|info| info.name)
+            .collect();
+        match matches.as_slice() {
+            [] => Err(anyhow!(
+                "No build directory configured with CMAKE_BUILD_TYPE={build_type}. Known: {:?}",
+                self.list_build_dirs()
+            )),
+            [name] => Ok(name.clone()),
+            _ => self
+                .detect_pwd_key()
+                .filter(|key| matches.contains(key))
+                .with_context(|| {
+                    format!(
+                        "Several build directories use CMAKE_BUILD_TYPE={build_type}: {matches:?}; pick one with --build"
+                    )
+                }),
+        }
+    }
+
+    /// [`BuildDirInfo::summary`] keyed by build dir name, for picker previews.
+    pub fn build_dir_previews(&self) -> HashMap<String, String> {
+        self.build_dir_info()
+            .into_iter()
+            .map(|info| (info.name.clone(), info.summary()))
+            .collect()
+    }
+
     fn prepare_cmake_file_api(&self, build_dir: &Path) -> Result<()> {
         let query_dir = build_dir.join(".cmake/api/v1/query");
         std::fs::create_dir_all(&query_dir)?;
@@ -169,22 +561,219 @@ impl CMakeProject {
         Ok(())
     }
 
-    pub async fn refresh_build_dir(&self, build_dir_name: Option<&str>) -> Result<()> {
+    pub async fn refresh_build_dir(&self, build_dir_name: Option<&str>) -> Result<ConfigureResult> {
+        let build_dir = match build_dir_name {
+            Some(name) => self.get_build_dir(name)?,
+            None => self.get_build_dir_from_input().await?,
+        };
+        self.configure(build_dir, &[]).await
+    }
+
+    /// Configure the build dir with `CMAKE_TOOLCHAIN_FILE=<toolchain>`, the
+    /// `-D` cache `definitions` (keys are `NAME` or `NAME:TYPE`) and, when
+    /// given, `-G <generator>`. Unless `force` or `fresh`, nothing happens
+    /// (and `None` is returned) when the cache already records that
+    /// toolchain file, generator and every definition's value. `fresh`, or
+    /// switching from a different toolchain or generator, passes `--fresh`,
+    /// since CMake ignores a changed toolchain file on an existing cache and
+    /// refuses a changed generator.
+    pub async fn configure_with(
+        &self,
+        build_dir_name: Option<&str>,
+        toolchain: Option<&Path>,
+        definitions: &BTreeMap<String, String>,
+        generator: Option<&str>,
+        force: bool,
+        fresh: bool,
+    ) -> Result<Option<ConfigureResult>> {
         let build_dir = match build_dir_name {
             Some(name) => self.get_build_dir(name)?,
             None => self.get_build_dir_from_input().await?,
         };
+        let cached =
+            read_cache_variable(build_dir, "CMAKE_TOOLCHAIN_FILE")?.filter(|v| !v.is_empty());
+        let toolchain_changed =
+            toolchain.is_some_and(|t| cached.as_deref().is_none_or(|c| Path::new(c) != t));
+        let cached_generator =
+            read_cache_variable(build_dir, "CMAKE_GENERATOR")?.filter(|v| !v.is_empty());
+        let generator_changed =
+            generator.is_some_and(|g| cached_generator.as_deref().is_some_and(|c| c != g));
+        let mut definitions_changed = false;
+        for (key, value) in definitions {
+            let name = key.split_once(':').map_or(key.as_str(), |(name, _)| name);
+            definitions_changed |= read_cache_variable(build_dir, name)?.as_ref() != Some(value);
+        }
+        // `*_INIT` flags only seed a new cache, so changing them needs a fresh one.
+        let flags_changed = if build_dir.join("CMakeCache.txt").is_file() {
+            let cache = read_all_cache_variables(build_dir)?;
+            MANAGED_FLAG_VARS
+                .iter()
+                .any(|var| cache.get(*var) != definitions.get(*var))
+        } else {
+            false
+        };
+        if !force
+            && !fresh
+            && !toolchain_changed
+            && !generator_changed
+            && !definitions_changed
+            && !flags_changed
+        {
+            return Ok(None);
+        }
+        let mut args: Vec<String> = definitions
+            .iter()
+            .map(|(key, value)| format!("-D{key}={value}"))
+            .collect();
+        if let Some(toolchain) = toolchain {
+            args.push(format!("-DCMAKE_TOOLCHAIN_FILE={}", toolchain.display()));
+        }
+        if let Some(generator) = generator {
+            args.extend(["-G".to_string(), generator.to_string()]);
+        }
+        let reason = if fresh {
+            None
+        } else if cached.is_some() && toolchain_changed {
+            Some("Toolchain file")
+        } else if generator_changed {
+            Some("Generator")
+        } else if flags_changed {
+            Some("Compiler flags")
+        } else {
+            None
+        };
+        if let Some(reason) = reason {
+            println!(
+                "{reason} changed; reconfiguring {} from scratch",
+                build_dir.display()
+            );
+        }
+        if fresh || reason.is_some() {
+            args.push("--fresh".to_string());
+        }
+        self.configure(build_dir, &args).await.map(Some)
+    }
 
+    /// Run `cmake -S <root> -B <build_dir> <extra_args>...`.
+    ///
+    /// Unless `[build] export_compile_commands = false`, a build dir whose
+    /// cache has never set `CMAKE_EXPORT_COMPILE_COMMANDS` (including a fresh
+    /// one) gets it turned on so clangd works out of the box. An explicit
+    /// value already in the cache is left alone.
+    ///
+    /// CMake prints "Build files have been written to" on every run, so
+    /// whether anything changed is judged by the generator's top-level file
+    /// (`build.ninja` or `Makefile`): CMake only rewrites it when its content
+    /// differs.
+    async fn configure(&self, build_dir: &Path, extra_args: &[String]) -> Result<ConfigureResult> {
+        let generated = || ["build.ninja", "Makefile"].map(|f| ninja::mtime(&build_dir.join(f)));
+        let before = generated();
         let mut cmd = Command::new("cmake");
-        cmd.args([
-            "-S",
-            &self.project_root.to_string_lossy(),
-            "-B",
-            &build_dir.to_string_lossy(),
-        ]);
+        cmd.arg("-S")
+            .arg(&self.project_root)
+            .arg("-B")
+            .arg(build_dir);
+        if self.build_config.export_compile_commands()
+            && read_cache_variable(build_dir, "CMAKE_EXPORT_COMPILE_COMMANDS")
+                .ok()
+                .flatten()
+                .is_none()
+        {
+            cmd.arg("-DCMAKE_EXPORT_COMPILE_COMMANDS=ON");
+        }
+        cmd.args(extra_args);
         self.env_config
             .apply_to_command(&mut cmd, &self.env_config.build_env(Some(build_dir)));
-        cmd.output().await?;
+        self.target_cache().invalidate(build_dir);
+        let output = cmd.output().await.map_err(cmake_spawn_error)?;
+        if !output.status.success() {
+            return Err(anyhow!(
+                "cmake configure failed ({}):\n{}",
+                output.status,
+                String::from_utf8_lossy(&output.stderr)
+            ));
+        }
+        Ok(ConfigureResult {
+            changed: generated() != before,
+            build_dir: build_dir.to_path_buf(),
+            generator: read_cache_variable(build_dir, "CMAKE_GENERATOR")?.unwrap_or_default(),
+        })
+    }
+
+    /// The newest `CMakeLists.txt` or included `.cmake` file of the project
+    /// if it was modified after `build_dir`'s `CMakeCache.txt` was last
+    /// written. Only the files the generator reruns CMake for are checked
+    /// (see [`regeneration_inputs`]), and of those only the ones in the
+    /// project's sources; before the first generate that is just the root
+    /// `CMakeLists.txt`.
+    pub fn stale_cmake_lists(&self, build_dir: &Path) -> Option<PathBuf> {
+        let cache_mtime = ninja::mtime(&build_dir.join("CMakeCache.txt"))?;
+        let mut inputs: Vec<PathBuf> = regeneration_inputs(build_dir)
+            .into_iter()
+            .filter(|p| p.starts_with(&self.project_root) && !p.starts_with(build_dir))
+            .collect();
+        if inputs.is_empty() {
+            inputs.push(self.project_root.join("CMakeLists.txt"));
+        }
+        inputs
+            .into_iter()
+            .filter_map(|p| Some((ninja::mtime(&p)?, p)))
+            .max_by_key(|(mtime, _)| *mtime)
+            .filter(|(mtime, _)| *mtime > cache_mtime)
+            .map(|(_, path)| path)
+    }
+
+    /// Make sure `compile_commands.json` exists in the build dir, turning on
+    /// `CMAKE_EXPORT_COMPILE_COMMANDS` and reconfiguring only when the cache
+    /// doesn't already have it enabled (or the file is missing).
+    pub async fn ensure_compile_commands(&self, build_dir_name: Option<&str>) -> Result<PathBuf> {
+        let build_dir = match build_dir_name {
+            Some(name) => self.get_build_dir(name)?,
+            None => self.get_build_dir_from_input().await?,
+        };
+        self.ensure_compile_commands_in(build_dir).await
+    }
+
+    async fn ensure_compile_commands_in(&self, build_dir: &Path) -> Result<PathBuf> {
+        let cdb = build_dir.join("compile_commands.json");
+        let enabled = read_cache_variable(build_dir, "CMAKE_EXPORT_COMPILE_COMMANDS")?
+            .is_some_and(|v| is_cmake_true(&v));
+        if !enabled || !cdb.try_exists()? {
+            self.configure(
+                build_dir,
+                &["-DCMAKE_EXPORT_COMPILE_COMMANDS=ON".to_string()],
+            )
+            .await?;
+        }
+        if !cdb.try_exists()? {
+            return Err(anyhow!(
+                "compile_commands.json still missing in {} after configure",
+                build_dir.display()
+            ));
+        }
+        Ok(cdb)
+    }
+
+    /// Make `<project_root>/compile_commands.json` a symlink to the build
+    /// dir's, for LSP servers that only look in the root. Falls back to a
+    /// copy when the symlink can't be created.
+    pub async fn link_compile_commands(&self, build_dir_name: Option<&str>) -> Result<()> {
+        let cdb = self.ensure_compile_commands(build_dir_name).await?;
+        let link = self.project_root.join("compile_commands.json");
+        if link == cdb || std::fs::read_link(&link).is_ok_and(|t| t == cdb) {
+            return Ok(());
+        }
+        if link.symlink_metadata().is_ok() {
+            std::fs::remove_file(&link)
+                .with_context(|| format!("Failed to remove {}", link.display()))?;
+        }
+        if symlink(&cdb, &link).is_ok() {
+            println!("{} -> {}", link.display(), cdb.display());
+        } else {
+            std::fs::copy(&cdb, &link)
+                .with_context(|| format!("Failed to copy {} to the project root", cdb.display()))?;
+            println!("Copied {} to {}", cdb.display(), link.display());
+        }
         Ok(())
     }
 
@@ -211,55 +800,173 @@ impl CMakeProject {
         Ok(reply)
     }
 
-    pub async fn collect_executable_targets(
-        &self,
-        build_dir_name: Option<&str>,
-    ) -> Result<Vec<Target>> {
+    /// Every target in the File API reply, regardless of type. The reply is
+    /// read once per build dir and then served from the target cache.
+    pub async fn collect_all_targets(&self, build_dir_name: Option<&str>) -> Result<Vec<Target>> {
         let build_dir = match build_dir_name {
             Some(name) => self.get_build_dir(name)?,
             None => self.get_build_dir_from_input().await?,
         };
+        if let Some(targets) = self.target_cache().get(build_dir) {
+            return Ok(targets.clone());
+        }
 
         let reply = self.collect_target_reply(build_dir_name).await?;
         let mut targets = Vec::new();
         for reply in reply {
             let path = build_dir.join(".cmake/api/v1/reply/").join(&reply);
             let content = std::fs::read_to_string(path)?;
-            let target = serde_json::from_str::<Target>(&content)?;
-            if target.is_executable() && target.artifacts.is_some() {
-                targets.push(target);
-            }
+            targets.push(serde_json::from_str::<Target>(&content)?);
         }
+        self.target_cache().insert(build_dir, targets.clone());
         Ok(targets)
     }
 
+    pub async fn collect_executable_targets(
+        &self,
+        build_dir_name: Option<&str>,
+    ) -> Result<Vec<Target>> {
+        Ok(self
+            .collect_all_targets(build_dir_name)
+            .await?
+            .into_iter()
+            .filter(|target| target.is_executable() && target.artifacts.is_some())
+            .collect())
+    }
+
+    /// Executable targets whose name matches one of the glob `patterns`
+    /// (see `[bench] patterns`).
+    pub async fn find_benchmark_targets(
+        &self,
+        build_dir_name: Option<&str>,
+        patterns: &[String],
+    ) -> Result<Vec<Target>> {
+        let matcher = benchmark_matcher(patterns)?;
+        Ok(self
+            .collect_executable_targets(build_dir_name)
+            .await?
+            .into_iter()
+            .filter(|target| matcher.is_match(&target.name))
+            .collect())
+    }
+
+    /// Build `targets` with a single `cmake --build`, showing its output as
+    /// `output` says, then run the `[[hooks.post_build]]` that apply to them.
+    /// `tool_args` go to the native build tool after `--`.
     pub async fn build_target(
         &self,
-        target: &str,
+        targets: &[String],
         build_dir_name: Option<&str>,
         jobs: usize,
+        output: BuildOutput,
+        tool_args: &[String],
     ) -> Result<()> {
         let build_dir = match build_dir_name {
             Some(name) => self.get_build_dir(name)?,
             None => self.get_build_dir_from_input().await?,
         };
 
+        if let Some(lists) = self.stale_cmake_lists(build_dir) {
+            if self.build_config.auto_reconfigure {
+                self.configure(build_dir, &[]).await?;
+            } else {
+                eprintln!(
+                    "warning: {} changed since {} was last configured; run `cmk configure`",
+                    lists
+                        .strip_prefix(&self.project_root)
+                        .unwrap_or(&lists)
+                        .display(),
+                    build_dir.display()
+                );
+            }
+        }
+
         let mut cmd = Command::new("cmake");
-        cmd.args([
-            "--build",
-            &build_dir.to_string_lossy(),
-            "--target",
-            target,
-            "-j",
-            &jobs.to_string(),
-        ]);
+        cmd.arg("--build")
+            .arg(build_dir)
+            .arg("--target")
+            .args(targets)
+            .args(["-j", &jobs.to_string()]);
+        if !tool_args.is_empty() {
+            cmd.arg("--").args(tool_args);
+        }
         self.env_config
             .apply_to_command(&mut cmd, &self.env_config.build_env(Some(build_dir)));
-        let mut child = cmd.spawn()?;
+        let progress = output == BuildOutput::Progress && is_ninja_build_dir(build_dir);
+        if progress {
+            cmd.env("NINJA_STATUS", ninja::NINJA_STATUS)
+                .stdout(Stdio::piped());
+        } else if output == BuildOutput::Stderr {
+            cmd.stdout(std::io::stderr());
+        }
+        let mut child = cmd.spawn().map_err(cmake_spawn_error)?;
+        let reporter = child
+            .stdout
+            .take()
+            .filter(|_| progress)
+            .map(|stdout| tokio::spawn(ninja::report_progress(stdout)));
         let ret = wait_with_cancel(&mut child).await?;
+        if let Some(reporter) = reporter {
+            reporter.await??;
+        }
         if !ret.success() {
             return Err(anyhow!("{}", ret));
         }
+        let hooks: Vec<&Hook> = self
+            .hooks
+            .post_build
+            .iter()
+            .filter(|hook| hook.applies_to(targets))
+            .collect();
+        let env = self.env_config.build_env(Some(build_dir));
+        self.run_hooks(
+            "post-build",
+            &hooks,
+            build_dir,
+            &env,
+            output == BuildOutput::Stderr,
+        )
+        .await
+    }
+
+    /// Run `hooks` in order with `sh -c` from the project root, with `env`
+    /// applied. A hook exiting non-zero is an error unless it allows
+    /// failure. With `to_stderr`, their stdout goes to stderr.
+    async fn run_hooks(
+        &self,
+        kind: &str,
+        hooks: &[&Hook],
+        build_dir: &Path,
+        env: &HashMap<String, String>,
+        to_stderr: bool,
+    ) -> Result<()> {
+        for hook in hooks {
+            let command = hook.expanded_command(&self.project_root, build_dir);
+            eprintln!("Running {kind} hook: {command}");
+            // The shell expands the placeholders from the environment, so
+            // paths with spaces survive a quoted "${PROJECT_BUILD_ROOT}".
+            let mut cmd = Command::new("sh");
+            cmd.args(["-c", &hook.command])
+                .current_dir(&self.project_root)
+                .env("PROJECT_ROOT", &self.project_root)
+                .env("PROJECT_BUILD_ROOT", build_dir);
+            if to_stderr {
+                cmd.stdout(std::io::stderr());
+            }
+            self.env_config.apply_to_command(&mut cmd, env);
+            let mut child = cmd
+                .spawn()
+                .with_context(|| format!("Failed to run {kind} hook `{command}`"))?;
+            let status = wait_with_cancel(&mut child).await?;
+            if status.success() {
+                continue;
+            }
+            if hook.allow_failure {
+                eprintln!("warning: {kind} hook `{command}` failed ({status})");
+            } else {
+                return Err(anyhow!("{kind} hook `{command}` failed ({status})"));
+            }
+        }
         Ok(())
     }
 
@@ -270,12 +977,16 @@ impl CMakeProject {
         };
 
         let mut cmd = Command::new("cmake");
-        cmd.args(["--build", &build_dir.to_string_lossy(), "--target", target])
+        // Stdin stays with cmk so the target run afterwards gets it.
+        cmd.arg("--build")
+            .arg(build_dir)
+            .args(["--target", target])
+            .stdin(Stdio::null())
             .stdout(Stdio::null())
             .stderr(Stdio::null());
         self.env_config
             .apply_to_command(&mut cmd, &self.env_config.build_env(Some(build_dir)));
-        let mut child = cmd.spawn()?;
+        let mut child = cmd.spawn().map_err(cmake_spawn_error)?;
         let ret = wait_with_cancel(&mut child).await?;
         if !ret.success() {
             return Err(anyhow!("{}", ret));
@@ -283,45 +994,171 @@ impl CMakeProject {
         Ok(())
     }
 
+    /// Optimistic check that `target`'s artifact is newer than everything
+    /// Ninja knows it depends on, so `run_target` can skip spawning cmake.
+    ///
+    /// The artifact must appear in `.ninja_log` and its mtime must not be
+    /// older than `build.ninja`, any `CMakeLists.txt` or `.cmake` file Ninja
+    /// would rerun CMake for, or any input recorded in `.ninja_deps` (sources
+    /// and headers of every object, which over-approximates the target's own
+    /// inputs). Any missing piece means "not up to date" and the caller falls
+    /// back to a real build.
+    pub async fn is_artifact_up_to_date(
+        &self,
+        target: &Target,
+        build_dir_name: Option<&str>,
+    ) -> Result<bool> {
+        let build_dir = match build_dir_name {
+            Some(name) => self.get_build_dir(name)?,
+            None => self.get_build_dir_from_input().await?,
+        };
+        let Some(artifact) = target.artifacts.as_ref().and_then(|a| a.first()) else {
+            return Ok(false);
+        };
+
+        let log = match std::fs::read_to_string(build_dir.join(".ninja_log")) {
+            Ok(log) => log,
+            Err(_) => return Ok(false),
+        };
+        if !ninja::parse_ninja_log(&log).contains_key(&artifact.path) {
+            return Ok(false);
+        }
+        let Some(artifact_mtime) = ninja::mtime(&build_dir.join(&artifact.path)) else {
+            return Ok(false);
+        };
+
+        let regeneration_inputs = ninja::read_regeneration_inputs(build_dir)?;
+        if regeneration_inputs.is_empty() {
+            return Ok(false);
+        }
+        let mut inputs = ninja::read_ninja_deps_inputs(build_dir)?;
+        inputs.push(build_dir.join("build.ninja"));
+        inputs.extend(regeneration_inputs);
+        for input in &inputs {
+            match ninja::mtime(input) {
+                Some(m) if m <= artifact_mtime => {}
+                _ => return Ok(false),
+            }
+        }
+        Ok(true)
+    }
+
+    /// Build `target` if needed, then run it with `args`. The build runs
+    /// with stdin closed; the target gets stdin as `stdin` says, so
+    /// `echo hello | cmk run foo` reaches `foo`. With `timeout`, a target
+    /// still running when it expires is stopped and the result is a
+    /// [`cmk_core::TimedOut`] error.
+    ///
+    /// The `[[hooks.pre_run]]` for the target run first, and its
+    /// `[[hooks.post_run]]` after it exits, whether it failed or not.
     pub async fn run_target(
         &self,
         target: &Target,
         args: &[String],
         build_dir_name: Option<&str>,
+        stdin: StdinMode,
+        timeout: Option<Duration>,
     ) -> Result<()> {
         let build_dir = match build_dir_name {
             Some(name) => self.get_build_dir(name)?,
             None => self.get_build_dir_from_input().await?,
         };
 
-        self.build_target_silent(&target.name, build_dir_name)
+        let up_to_date = self
+            .is_artifact_up_to_date(target, build_dir_name)
+            .await
+            .unwrap_or(false);
+        if !up_to_date {
+            self.build_target_silent(&target.name, build_dir_name)
+                .await?;
+        }
+        let path = target
+            .artifact_path(build_dir)
+            .with_context(|| format!("Target {} has no artifact", target.name))?;
+        let mut env = self.env_config.run_env(Some(&target.name), Some(build_dir));
+        let targets = std::slice::from_ref(&target.name);
+        let pre_run: Vec<&Hook> = self
+            .hooks
+            .pre_run
+            .iter()
+            .filter(|hook| hook.applies_to(targets))
+            .collect();
+        self.run_hooks("pre-run", &pre_run, build_dir, &env, false)
             .await?;
-        let path = build_dir.join(&target.artifacts.as_ref().unwrap()[0].path);
+
         let mut cmd = Command::new(path);
-        cmd.args(args);
-        self.env_config.apply_to_command(
-            &mut cmd,
-            &self.env_config.run_env(Some(&target.name), Some(build_dir)),
-        );
+        cmd.args(args).stdin(match stdin {
+            StdinMode::Inherit => Stdio::inherit(),
+            StdinMode::Null => Stdio::null(),
+        });
+        self.env_config.apply_to_command(&mut cmd, &env);
         let mut child = cmd.spawn()?;
-        let ret = wait_with_cancel(&mut child).await?;
+        let waited = wait_foreground_timeout(&mut child, timeout).await;
+
+        let post_run: Vec<&Hook> = self
+            .hooks
+            .post_run
+            .iter()
+            .filter(|hook| hook.applies_to(targets))
+            .collect();
+        let exit_code = match &waited {
+            Ok(status) if status.success() => 0,
+            Ok(status) => ChildFailed(*status).exit_code(),
+            Err(e) if e.is::<TimedOut>() => 124,
+            Err(_) => 1,
+        };
+        env.insert(RUN_EXIT_CODE_ENV.to_string(), exit_code.to_string());
+        let hooks_result = self
+            .run_hooks("post-run", &post_run, build_dir, &env, false)
+            .await;
+        let ret = waited?;
         if !ret.success() {
-            return Err(anyhow!("{}", ret));
+            if let Err(e) = hooks_result {
+                eprintln!("warning: {e:#}");
+            }
+            return Err(ChildFailed(ret).into());
         }
-        Ok(())
+        hooks_result
     }
 
-    pub async fn list_all_translation_units(
+    /// Object files of the build dir (paths relative to it) whose source has
+    /// one of `extensions` (default [`DEFAULT_TU_EXTENSIONS`]), so assembly,
+    /// CUDA or Fortran objects are left out. CMake names objects
+    /// `<source>.o`, so the source extension is read off the object path.
+    ///
+    /// Ninja build dirs are listed with `ninja -t targets`; any other
+    /// generator (Makefiles) goes through the object files named in
+    /// `compile_commands.json`.
+    pub async fn translation_units(
         &self,
         build_dir_name: Option<&str>,
+        extensions: Option<&[&str]>,
     ) -> Result<Vec<String>> {
+        let extensions = extensions.unwrap_or(DEFAULT_TU_EXTENSIONS);
         let build_dir = match build_dir_name {
             Some(name) => self.get_build_dir(name)?,
             None => self.get_build_dir_from_input().await?,
         };
+        let objects = if is_ninja_build_dir(build_dir) {
+            self.ninja_objects(build_dir).await?
+        } else {
+            self.compdb_objects(build_dir).await?
+        };
+        Ok(objects
+            .into_iter()
+            .filter(|tu| {
+                Path::new(tu.trim_end_matches(".o"))
+                    .extension()
+                    .is_some_and(|ext| extensions.iter().any(|e| ext == *e))
+            })
+            .collect())
+    }
 
+    async fn ninja_objects(&self, build_dir: &Path) -> Result<Vec<String>> {
         let mut cmd = Command::new("ninja");
-        cmd.args(["-C", &build_dir.to_string_lossy(), "-t", "targets", "all"])
+        cmd.arg("-C")
+            .arg(build_dir)
+            .args(["-t", "targets", "all"])
             .stdout(Stdio::piped());
         self.env_config
             .apply_to_command(&mut cmd, &self.env_config.build_env(Some(build_dir)));
@@ -334,14 +1171,152 @@ impl CMakeProject {
             .collect())
     }
 
-    pub async fn build_tu(&self, tu: &str, build_dir_name: Option<&str>) -> Result<()> {
+    /// The object file of every compile command, relative to `build_dir`.
+    /// See [`CompDBEntry::object`] for how it is found.
+    async fn compdb_objects(&self, build_dir: &Path) -> Result<Vec<String>> {
+        let entries = self.read_compdb(build_dir).await?;
+        let objects: Vec<String> = entries
+            .iter()
+            .filter_map(|entry| {
+                let output = entry.directory.join(entry.object()?);
+                let relative = output.strip_prefix(build_dir).ok()?;
+                Some(relative.to_string_lossy().to_string())
+            })
+            .collect();
+        if objects.is_empty() && !entries.is_empty() {
+            return Err(anyhow!(
+                "No object files found in {}'s compile_commands.json: it has neither \
+                 `output` fields (CMake 3.20+) nor `-o` arguments",
+                build_dir.display()
+            ));
+        }
+        Ok(objects)
+    }
+
+    /// Package name -> version of every package CPM recorded in the build
+    /// dir's cache, from `CPM_PACKAGE_<name>_VERSION` (or `CPM_<name>_VERSION`)
+    /// entries. CPM's own `CPM_DOWNLOAD_VERSION` is left out.
+    pub async fn detect_cpm_versions(
+        &self,
+        build: Option<&str>,
+    ) -> Result<HashMap<String, String>> {
+        let build_dir = match build {
+            Some(name) => self.get_build_dir(name)?,
+            None => self.get_build_dir_from_input().await?,
+        };
+        Ok(read_all_cache_variables(build_dir)?
+            .into_iter()
+            .filter_map(|(key, version)| {
+                let name = key.strip_prefix("CPM_")?.strip_suffix("_VERSION")?;
+                let name = name.strip_prefix("PACKAGE_").unwrap_or(name);
+                (!name.is_empty() && name != "DOWNLOAD" && !version.is_empty())
+                    .then(|| (name.to_string(), version))
+            })
+            .collect())
+    }
+
+    /// Every entry of the build dir's `compile_commands.json`, configuring
+    /// with `CMAKE_EXPORT_COMPILE_COMMANDS=ON` first if it is missing.
+    pub async fn load_compile_db(&self, build: Option<&str>) -> Result<Vec<CompDBEntry>> {
+        let build_dir = match build {
+            Some(name) => self.get_build_dir(name)?,
+            None => self.get_build_dir_from_input().await?,
+        };
+        self.read_compdb(build_dir).await
+    }
+
+    /// Where the build dir's `compile_commands.json` is (or would be)
+    /// written. Unlike [`Self::ensure_compile_commands`] this never
+    /// configures.
+    pub async fn compile_db_path(&self, build: Option<&str>) -> Result<PathBuf> {
+        let build_dir = match build {
+            Some(name) => self.get_build_dir(name)?,
+            None => self.get_build_dir_from_input().await?,
+        };
+        Ok(build_dir.join("compile_commands.json"))
+    }
+
+    async fn read_compdb(&self, build_dir: &Path) -> Result<Vec<CompDBEntry>> {
+        let cdb = self.ensure_compile_commands_in(build_dir).await?;
+        let content = std::fs::read_to_string(&cdb)
+            .with_context(|| format!("Failed to read {}", cdb.display()))?;
+        serde_json::from_str(&content).with_context(|| format!("Failed to parse {}", cdb.display()))
+    }
+
+    /// The `compile_commands.json` entry for `tu`, given either as an object
+    /// path relative to the build dir (what `build_tu` takes) or as a source
+    /// file path.
+    pub async fn find_compile_command(
+        &self,
+        tu: &str,
+        build: Option<&str>,
+    ) -> Result<Option<CompDBEntry>> {
+        let build_dir = match build {
+            Some(name) => self.get_build_dir(name)?,
+            None => self.get_build_dir_from_input().await?,
+        };
+        let object = build_dir.join(tu);
+        Ok(self
+            .read_compdb(build_dir)
+            .await?
+            .into_iter()
+            .find(|entry| {
+                entry
+                    .output
+                    .as_ref()
+                    .is_some_and(|output| entry.directory.join(output) == object)
+                    || entry.directory.join(&entry.file).ends_with(tu)
+            }))
+    }
+
+    /// Group `translation_units` by owning target. CMake places a
+    /// target's objects under `<subdir>/CMakeFiles/<target>.dir/`, so the
+    /// target name is recovered from that path component.
+    pub async fn list_translation_units_by_target(
+        &self,
+        build_dir_name: Option<&str>,
+    ) -> Result<HashMap<String, Vec<String>>> {
+        let mut grouped: HashMap<String, Vec<String>> = HashMap::new();
+        for tu in self.translation_units(build_dir_name, None).await? {
+            let target = tu
+                .split('/')
+                .collect::<Vec<_>>()
+                .windows(2)
+                .find(|w| w[0] == "CMakeFiles" && w[1].ends_with(".dir"))
+                .map(|w| w[1].trim_end_matches(".dir").to_string())
+                .unwrap_or_default();
+            grouped.entry(target).or_default().push(tu);
+        }
+        Ok(grouped)
+    }
+
+    pub async fn build_tu(
+        &self,
+        tu: &str,
+        build_dir_name: Option<&str>,
+        jobs: usize,
+    ) -> Result<()> {
         let build_dir = match build_dir_name {
             Some(name) => self.get_build_dir(name)?,
             None => self.get_build_dir_from_input().await?,
         };
 
-        let mut cmd = Command::new("ninja");
-        cmd.args(["-C", &build_dir.to_string_lossy(), tu]);
+        let mut cmd = if is_ninja_build_dir(build_dir) {
+            let mut cmd = Command::new("ninja");
+            cmd.arg("-C")
+                .arg(build_dir)
+                .args(["-j", &jobs.to_string(), tu]);
+            cmd
+        } else {
+            // Makefiles: run the target's `build.make` from the top build
+            // dir, as CMake's own Makefiles do; its paths are relative to it.
+            let makefile =
+                object_makefile(tu).with_context(|| format!("Unexpected object path {tu}"))?;
+            let mut cmd = Command::new("make");
+            cmd.arg("-C").arg(build_dir).arg("-f").arg(makefile);
+            cmd.arg("-j").arg(jobs.to_string()).arg(tu);
+            cmd
+        };
         self.env_config
             .apply_to_command(&mut cmd, &self.env_config.build_env(Some(build_dir)));
         let mut child = cmd.spawn()?;
@@ -353,21 +1328,520 @@ impl CMakeProject {
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[cfg(unix)]
+fn symlink(target: &Path, link: &Path) -> std::io::Result<()> {
+    std::os::unix::fs::symlink(target, link)
+}
+
+#[cfg(windows)]
+fn symlink(_target: &Path, _link: &Path) -> std::io::Result<()> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "windows is not a supported cmk platform",
+    ))
+}
+
+/// The `build.make` holding the rule for the Makefiles object `tu` (relative
+/// to the build dir), e.g. `src/CMakeFiles/app.dir/build.make` for
+/// `src/CMakeFiles/app.dir/util/str.cc.o`.
+fn object_makefile(tu: &str) -> Option<String> {
+    let start = tu.find("CMakeFiles/")?;
+    let end = start + tu[start..].find(".dir/")? + ".dir".len();
+    Some(format!("{}/build.make", &tu[..end]))
+}
+
+/// How [`CMakeProject::build_target`] shows the build's output.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BuildOutput {
+    /// The build tool's output, unchanged, on stdout.
+    #[default]
+    Passthrough,
+    /// A progress bar driven by Ninja's status lines, with compiler output
+    /// printed above it. Other generators pass through.
+    Progress,
+    /// Everything on stderr, leaving stdout to the caller.
+    Stderr,
+}
+
+/// What a target started by [`CMakeProject::run_target`] reads as stdin.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum StdinMode {
+    /// cmk's own stdin: the terminal, or whatever is piped into cmk.
+    #[default]
+    Inherit,
+    /// Immediate end of file.
+    Null,
+}
+
+/// Outcome of a `cmake` configure run.
+#[derive(Debug, Clone)]
+pub struct ConfigureResult {
+    /// Whether the build files were regenerated; `false` for a no-op.
+    pub changed: bool,
+    pub build_dir: PathBuf,
+    /// `CMAKE_GENERATOR` from the cache, e.g. `Ninja`.
+    pub generator: String,
+}
+
+/// What a build dir's `CMakeCache.txt` says about how it was configured.
+#[derive(Debug, Clone)]
+pub struct BuildDirInfo {
+    pub name: String,
+    pub path: PathBuf,
+    pub generator: Option<String>,
+    pub build_type: Option<String>,
+    pub compiler: Option<String>,
+}
+
+impl BuildDirInfo {
+    /// One `key: value` line per field; unset fields show as `-`.
+    pub fn summary(&self) -> String {
+        let or_dash = |v: &Option<String>| v.clone().unwrap_or_else(|| "-".to_string());
+        format!(
+            "path: {}\ngenerator: {}\nbuild type: {}\ncompiler: {}",
+            self.path.display(),
+            or_dash(&self.generator),
+            or_dash(&self.build_type),
+            or_dash(&self.compiler),
+        )
+    }
+}
+
+/// One entry of `compile_commands.json`. Relative `file`/`output` paths are
+/// relative to `directory`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CompDBEntry {
+    pub directory: PathBuf,
+    pub file: PathBuf,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub command: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub arguments: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output: Option<String>,
+}
+
+impl CompDBEntry {
+    /// The object file this command writes, relative to `directory`:
+    /// `output` (CMake 3.20+), else the argument of `-o`.
+    pub fn object(&self) -> Option<String> {
+        if let Some(output) = &self.output {
+            return Some(output.clone());
+        }
+        let words: Vec<String> = match (&self.arguments, &self.command) {
+            (Some(arguments), _) => arguments.clone(),
+            (None, Some(command)) => command.split_whitespace().map(str::to_string).collect(),
+            (None, None) => return None,
+        };
+        words
+            .iter()
+            .position(|word| word == "-o")
+            .and_then(|i| words.get(i + 1).cloned())
+    }
+
+    /// The compiler invocation: `command`, or `arguments` joined by spaces.
+    pub fn command_line(&self) -> String {
+        match (&self.command, &self.arguments) {
+            (Some(command), _) => command.clone(),
+            (None, Some(arguments)) => arguments.join(" "),
+            (None, None) => String::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TargetArtifact {
     pub path: String,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+/// A `sources[]` entry of a File API target. `path` is relative to the
+/// top-level source dir unless absolute.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TargetSource {
+    pub path: String,
+}
+
+/// The `folder` of a File API target: its `FOLDER` property, e.g.
+/// `tools/codegen`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TargetFolder {
+    pub name: String,
+}
+
+/// A `dependencies[]` entry of a File API target: the `id` of a target it
+/// depends on. `backtrace` indexes the reply's backtrace graph when CMake
+/// recorded one.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TargetDependency {
+    pub id: String,
+    #[serde(default)]
+    pub backtrace: Option<usize>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Target {
     pub name: String,
+    /// Unique within a build dir, e.g. `app::@6890427a1f51a3e7e1df`.
+    #[serde(default)]
+    pub id: String,
     #[serde(rename = "type")]
     pub target_type: String,
     pub artifacts: Option<Vec<TargetArtifact>>,
+    #[serde(default)]
+    pub sources: Vec<TargetSource>,
+    #[serde(default)]
+    pub folder: Option<TargetFolder>,
+    #[serde(default)]
+    pub dependencies: Option<Vec<TargetDependency>>,
 }
 
 impl Target {
     pub fn is_executable(&self) -> bool {
         self.target_type == "EXECUTABLE"
     }
+
+    pub fn folder_name(&self) -> Option<&str> {
+        self.folder.as_ref().map(|f| f.name.as_str())
+    }
+
+    /// Whether the target's `FOLDER` is `folder` or nested below it.
+    pub fn in_folder(&self, folder: &str) -> bool {
+        let folder = folder.trim_end_matches('/');
+        self.folder_name().is_some_and(|name| {
+            name == folder
+                || name
+                    .strip_prefix(folder)
+                    .is_some_and(|rest| rest.starts_with('/'))
+        })
+    }
+
+    /// The artifact `cmk run` runs: the first one the File API lists (the
+    /// executable, ahead of e.g. a Windows import library), resolved
+    /// against `build_dir`.
+    pub fn artifact_path(&self, build_dir: &Path) -> Option<PathBuf> {
+        self.artifacts
+            .as_ref()
+            .and_then(|artifacts| artifacts.first())
+            .map(|artifact| build_dir.join(&artifact.path))
+    }
+
+    /// Absolute paths of the target's sources, resolved against `project_root`.
+    pub fn source_paths(&self, project_root: &Path) -> Vec<PathBuf> {
+        self.sources
+            .iter()
+            .map(|s| project_root.join(&s.path))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn makefile_depends_are_listed() {
+        let content = "# CMAKE generated file: DO NOT EDIT!\n\
+                       set(CMAKE_DEPENDS_GENERATOR \"Unix Makefiles\")\n\
+                       set(CMAKE_MAKEFILE_DEPENDS\n  \
+                         \"CMakeCache.txt\"\n  \
+                         \"/s/CMakeLists.txt\"\n  \
+                         \"/s/cmake/Utils.cmake\"\n  \
+                         )\n\
+                       set(CMAKE_MAKEFILE_OUTPUTS\n  \"Makefile\"\n  )\n";
+        assert_eq!(
+            parse_makefile_depends(content),
+            [
+                "CMakeCache.txt",
+                "/s/CMakeLists.txt",
+                "/s/cmake/Utils.cmake"
+            ]
+        );
+        assert!(parse_makefile_depends("").is_empty());
+    }
+
+    #[test]
+    fn object_makefile_is_the_target_dirs_build_make() {
+        assert_eq!(
+            object_makefile("CMakeFiles/app.dir/main.cc.o").as_deref(),
+            Some("CMakeFiles/app.dir/build.make")
+        );
+        assert_eq!(
+            object_makefile("src/CMakeFiles/app.dir/util/str.cc.o").as_deref(),
+            Some("src/CMakeFiles/app.dir/build.make")
+        );
+        assert_eq!(object_makefile("main.o"), None);
+    }
+
+    #[test]
+    fn compdb_object_falls_back_to_dash_o() {
+        let entry =
+            |command: Option<&str>, arguments: Option<&[&str]>, output: Option<&str>| CompDBEntry {
+                directory: PathBuf::from("/b/src"),
+                file: PathBuf::from("/s/src/main.cc"),
+                command: command.map(str::to_string),
+                arguments: arguments.map(|a| a.iter().map(|s| s.to_string()).collect()),
+                output: output.map(str::to_string),
+            };
+        assert_eq!(
+            entry(None, None, Some("CMakeFiles/app.dir/main.cc.o"))
+                .object()
+                .as_deref(),
+            Some("CMakeFiles/app.dir/main.cc.o")
+        );
+        assert_eq!(
+            entry(
+                Some("/usr/bin/c++ -O2 -o CMakeFiles/app.dir/main.cc.o -c /s/src/main.cc"),
+                None,
+                None
+            )
+            .object()
+            .as_deref(),
+            Some("CMakeFiles/app.dir/main.cc.o")
+        );
+        assert_eq!(
+            entry(None, Some(&["cc", "-o", "x.o", "-c", "x.c"]), None)
+                .object()
+                .as_deref(),
+            Some("x.o")
+        );
+        assert_eq!(entry(Some("cc -c x.c"), None, None).object(), None);
+    }
+
+    #[test]
+    fn rev_parse_path_keeps_spaces_and_newlines() {
+        assert_eq!(
+            parse_rev_parse_path("/home/me/my project \n"),
+            Some(PathBuf::from("/home/me/my project "))
+        );
+        assert_eq!(
+            parse_rev_parse_path("/tmp/a\nb\n"),
+            Some(PathBuf::from("/tmp/a\nb"))
+        );
+        assert_eq!(
+            parse_rev_parse_path("C:/src/app\r\n"),
+            Some(PathBuf::from("C:/src/app"))
+        );
+        assert_eq!(parse_rev_parse_path("\n"), None);
+        assert_eq!(parse_rev_parse_path(""), None);
+    }
+
+    #[test]
+    fn preferred_build_dir_skips_missing_defaults() {
+        let build_dirs: HashMap<String, PathBuf> = ["build", "out/release"]
+            .into_iter()
+            .map(|name| (name.to_string(), PathBuf::from("/p").join(name)))
+            .collect();
+        let pick = |candidates: &[Option<&str>]| preferred_build_dir(&build_dirs, candidates);
+        assert_eq!(
+            pick(&[Some("out/release"), Some("build")]),
+            Some(&PathBuf::from("/p/out/release"))
+        );
+        assert_eq!(
+            pick(&[Some("gone"), Some("build")]),
+            Some(&PathBuf::from("/p/build"))
+        );
+        assert_eq!(
+            pick(&[None, Some("build")]),
+            Some(&PathBuf::from("/p/build"))
+        );
+        assert_eq!(pick(&[Some("gone"), None]), None);
+    }
+
+    #[test]
+    fn build_dirs_found_breadth_first() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        for dir in [
+            "build",
+            "build/_deps/fmt-subbuild",
+            "out/debug",
+            "src/build",
+            "a/b/c/build",
+        ] {
+            std::fs::create_dir_all(root.join(dir)).unwrap();
+            std::fs::write(root.join(dir).join("CMakeCache.txt"), "").unwrap();
+        }
+        let found = CMakeProject::collect_build_dirs(root, 3).unwrap();
+        let mut names: Vec<&str> = found.keys().map(String::as_str).collect();
+        names.sort();
+        assert_eq!(names, ["build", "out/debug"]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinked_build_dir_is_found() {
+        let tmp = tempfile::tempdir().unwrap();
+        let base = tmp.path();
+        let root = base.join("project");
+        let outside = base.join("mybuild");
+        std::fs::create_dir_all(&root).unwrap();
+        std::fs::create_dir_all(&outside).unwrap();
+        std::fs::write(outside.join("CMakeCache.txt"), "").unwrap();
+        std::os::unix::fs::symlink(&outside, root.join("build")).unwrap();
+        std::os::unix::fs::symlink(base, root.join("loop")).unwrap();
+        let found = CMakeProject::collect_build_dirs(&root, 3).unwrap();
+        let expected = std::fs::canonicalize(&outside).unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found["build"], expected);
+    }
+
+    #[test]
+    fn target_cache_drops_reconfigured_build_dir() {
+        let target: Target =
+            serde_json::from_str(r#"{"name": "app", "type": "EXECUTABLE"}"#).unwrap();
+        let mut cache = TargetCache::default();
+        assert!(!cache.populated);
+        cache.insert(Path::new("/p/build"), vec![target.clone()]);
+        cache.insert(Path::new("/p/release"), vec![target]);
+        assert!(cache.populated);
+        assert!(cache.targets.contains_key("/p/release"));
+        assert_eq!(cache.get(Path::new("/p/build")).unwrap()[0].name, "app");
+        cache.invalidate(Path::new("/p/build"));
+        assert!(cache.get(Path::new("/p/build")).is_none());
+        assert!(cache.get(Path::new("/p/release")).is_some());
+    }
+
+    #[test]
+    fn benchmark_names_follow_convention() {
+        let patterns: Vec<String> = cmk_config::DEFAULT_BENCH_PATTERNS
+            .iter()
+            .map(|p| p.to_string())
+            .collect();
+        let matcher = benchmark_matcher(&patterns).unwrap();
+        assert!(matcher.is_match("bench_parser"));
+        assert!(matcher.is_match("parser_bench"));
+        assert!(matcher.is_match("parser_benchmark"));
+        assert!(!matcher.is_match("benchmark_utils"));
+        assert!(!matcher.is_match("parser_test"));
+
+        let matcher = benchmark_matcher(&["perf_*".to_string()]).unwrap();
+        assert!(matcher.is_match("perf_parser"));
+        assert!(!matcher.is_match("parser_bench"));
+    }
+
+    #[cfg(unix)]
+    fn block_on<F: std::future::Future>(future: F) -> F::Output {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+            .block_on(future)
+    }
+
+    /// A project at `root` whose `build` dir looks freshly built by Ninja,
+    /// with the shell `script` as the artifact of its `app` target, so
+    /// `run_target` runs it without invoking cmake.
+    #[cfg(unix)]
+    fn prebuilt_project(root: &Path, script: &str, hooks: HooksConfig) -> (CMakeProject, Target) {
+        use std::os::unix::fs::PermissionsExt;
+        let build_dir = root.join("build");
+        std::fs::create_dir_all(&build_dir).unwrap();
+        std::fs::write(root.join("CMakeLists.txt"), "").unwrap();
+        std::fs::write(
+            build_dir.join("CMakeCache.txt"),
+            "CMAKE_GENERATOR:INTERNAL=Ninja\n",
+        )
+        .unwrap();
+        std::fs::write(
+            build_dir.join("build.ninja"),
+            format!(
+                "build build.ninja: RERUN_CMAKE | {}\n",
+                root.join("CMakeLists.txt").display()
+            ),
+        )
+        .unwrap();
+        let mut deps = b"# ninjadeps\n".to_vec();
+        deps.extend(4u32.to_le_bytes());
+        std::fs::write(build_dir.join(".ninja_deps"), deps).unwrap();
+        std::fs::write(
+            build_dir.join(".ninja_log"),
+            "# ninja log v5\n0\t1\t0\tapp\t0\n",
+        )
+        .unwrap();
+        let app = build_dir.join("app");
+        std::fs::write(&app, format!("#!/bin/sh\n{script}\n")).unwrap();
+        std::fs::set_permissions(&app, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let project = CMakeProject {
+            project_root: root.to_path_buf(),
+            build_dirs: HashMap::from([("build".to_string(), build_dir)]),
+            env_config: BuildEnv::load(root).unwrap(),
+            build_config: BuildConfig::default(),
+            hooks,
+            target_cache: Mutex::default(),
+        };
+        let target = serde_json::from_str(
+            r#"{"name": "app", "type": "EXECUTABLE", "artifacts": [{"path": "app"}]}"#,
+        )
+        .unwrap();
+        (project, target)
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn run_target_with_null_stdin_sees_eof() {
+        let tmp = tempfile::tempdir().unwrap();
+        let ran = tmp.path().join("ran");
+        // Exits 3 if it could read a line, so an inherited stdin would fail
+        // (or hang until the timeout) instead of passing.
+        let script = format!("read line && exit 3\necho ok > '{}'", ran.display());
+        let (project, target) = prebuilt_project(tmp.path(), &script, HooksConfig::default());
+        block_on(project.run_target(
+            &target,
+            &[],
+            Some("build"),
+            StdinMode::Null,
+            Some(Duration::from_secs(10)),
+        ))
+        .unwrap();
+        assert_eq!(std::fs::read_to_string(ran).unwrap(), "ok\n");
+    }
+
+    #[cfg(unix)]
+    fn hook(command: &str, target: Option<&str>, allow_failure: bool) -> Hook {
+        Hook {
+            command: command.to_string(),
+            target: target.map(str::to_string),
+            allow_failure,
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn run_hooks_see_target_and_exit_status() {
+        let tmp = tempfile::tempdir().unwrap();
+        let hooks = HooksConfig {
+            pre_run: vec![
+                hook("echo pre >> \"${PROJECT_ROOT}/log\"", None, false),
+                hook("echo other >> log", Some("other"), false),
+            ],
+            post_run: vec![
+                hook("exit 1", None, true),
+                hook("echo post $CMK_RUN_EXIT_CODE >> log", Some("app"), false),
+            ],
+            ..Default::default()
+        };
+        let (project, target) = prebuilt_project(tmp.path(), "exit 5", hooks);
+        let err = block_on(project.run_target(&target, &[], Some("build"), StdinMode::Null, None))
+            .unwrap_err();
+        assert_eq!(err.downcast_ref::<ChildFailed>().unwrap().exit_code(), 5);
+        let log = std::fs::read_to_string(tmp.path().join("log")).unwrap();
+        assert_eq!(log, "pre\npost 5\n");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn failing_pre_run_hook_stops_the_run() {
+        let tmp = tempfile::tempdir().unwrap();
+        let hooks = HooksConfig {
+            pre_run: vec![hook("exit 2", None, false)],
+            ..Default::default()
+        };
+        let ran = tmp.path().join("ran");
+        let script = format!("touch '{}'", ran.display());
+        let (project, target) = prebuilt_project(tmp.path(), &script, hooks);
+        let err = block_on(project.run_target(&target, &[], Some("build"), StdinMode::Null, None))
+            .unwrap_err();
+        assert!(err.to_string().contains("pre-run hook `exit 2` failed"));
+        assert!(!ran.exists());
+    }
 }
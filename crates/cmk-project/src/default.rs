@@ -42,7 +42,7 @@ pub async fn load_template(template: Option<&str>) -> Result<Template> {
 
         if !entries.is_empty() {
             entries.insert(0, BUILTIN_NAME.to_string());
-            let chosen = completing_read(&entries).await?;
+            let chosen = completing_read(&entries, None).await?;
             if chosen == BUILTIN_NAME {
                 return Ok(Template::BuiltIn);
             }
@@ -55,16 +55,86 @@ pub async fn load_template(template: Option<&str>) -> Result<Template> {
     Ok(Template::BuiltIn)
 }
 
+/// Knobs for `Template::apply` beyond `{placeholder}` substitution.
+pub struct ScaffoldOptions {
+    /// Write the built-in `.gitignore`.
+    pub gitignore: bool,
+    /// Create `cmake/` with a starter find module and, for the built-in
+    /// template, add it to `CMAKE_MODULE_PATH`.
+    pub cmake_modules: bool,
+    /// Have the built-in template pin CPM packages in `package-lock.cmake`.
+    pub package_lock: bool,
+    /// Have the built-in template link with `-fuse-ld=<linker>`.
+    pub linker: Option<String>,
+    /// `-fsanitize=` names the built-in template enables for Debug builds
+    /// of the executable. Empty = no sanitizer options.
+    pub sanitizers: Vec<String>,
+}
+
+impl Default for ScaffoldOptions {
+    fn default() -> Self {
+        Self {
+            gitignore: true,
+            cmake_modules: false,
+            package_lock: true,
+            linker: None,
+            sanitizers: vec!["address".to_string(), "undefined".to_string()],
+        }
+    }
+}
+
 impl Template {
-    pub fn apply(&self, project_dir: &Path, vars: &HashMap<&str, &str>) -> Result<()> {
+    pub fn apply(
+        &self,
+        project_dir: &Path,
+        vars: &HashMap<&str, &str>,
+        options: &ScaffoldOptions,
+    ) -> Result<()> {
+        if options.cmake_modules {
+            std::fs::create_dir_all(project_dir.join("cmake"))?;
+            std::fs::write(project_dir.join("cmake/FindExample.cmake"), FIND_EXAMPLE)?;
+        }
         match self {
             Template::BuiltIn => {
                 std::fs::create_dir_all(project_dir.join("src"))?;
-                std::fs::write(project_dir.join(".gitignore"), GIT_IGNORE)?;
+                if options.gitignore {
+                    std::fs::write(project_dir.join(".gitignore"), GIT_IGNORE)?;
+                }
                 std::fs::write(project_dir.join(".clang-format"), CLANG_FORMAT_CONFIG)?;
                 std::fs::write(project_dir.join(".clang-tidy"), CLANG_TIDY_CONFIG)?;
                 std::fs::write(project_dir.join("src/main.cc"), MAIN_CC)?;
-                let cmake = substitute(CMAKE_LISTS, vars);
+                if options.package_lock {
+                    std::fs::write(project_dir.join("package-lock.cmake"), PACKAGE_LOCK)?;
+                }
+                let link_options = options
+                    .linker
+                    .as_ref()
+                    .map(|linker| format!("add_link_options(-fuse-ld={linker})\n"))
+                    .unwrap_or_default();
+                let sanitizers = sanitizer_options(
+                    vars.get("{name}").copied().unwrap_or_default(),
+                    &options.sanitizers,
+                );
+                let mut vars = vars.clone();
+                vars.insert(
+                    "{cmake_module_path}",
+                    if options.cmake_modules {
+                        CMAKE_MODULE_PATH
+                    } else {
+                        ""
+                    },
+                );
+                vars.insert(
+                    "{cpm_package_lock}",
+                    if options.package_lock {
+                        CPM_USE_PACKAGE_LOCK
+                    } else {
+                        ""
+                    },
+                );
+                vars.insert("{link_options}", &link_options);
+                vars.insert("{sanitizers}", &sanitizers);
+                let cmake = substitute(CMAKE_LISTS, &vars);
                 std::fs::write(project_dir.join("CMakeLists.txt"), cmake)?;
                 Ok(())
             }
@@ -73,6 +143,20 @@ impl Template {
     }
 }
 
+/// Debug-only `-fsanitize=` compile and link options for `target`, or
+/// nothing without `sanitizers`. Formatted here rather than substituted
+/// because the `{name}` placeholder may already have been replaced.
+fn sanitizer_options(target: &str, sanitizers: &[String]) -> String {
+    if sanitizers.is_empty() {
+        return String::new();
+    }
+    let flag = format!("$<$<CONFIG:Debug>:-fsanitize={}>", sanitizers.join(","));
+    format!(
+        "target_compile_options({target} PRIVATE {flag})\n\
+         target_link_options({target} PRIVATE {flag})\n"
+    )
+}
+
 fn substitute(content: &str, vars: &HashMap<&str, &str>) -> String {
     let mut result = content.to_string();
     for (key, value) in vars {
@@ -184,25 +268,33 @@ CheckOptions:
     value: true
 "#;
 
+/// fmtlib/fmt release the built-in template uses when neither the package
+/// index nor GitHub can tell a newer one, e.g. `cmk new --offline`.
+pub const DEFAULT_FMT_VERSION: &str = "12.1.0";
+
+/// GitHub packages the built-in template pulls in with `CPMAddPackage`, and
+/// the version used when no release can be looked up for them. The version
+/// picked fills the template's `{<repo>_version}` placeholder, e.g.
+/// `{fmt_version}`.
+pub const DEFAULT_CPM_PACKAGES: &[(&str, &str)] = &[("fmtlib/fmt", DEFAULT_FMT_VERSION)];
+
 pub const CMAKE_LISTS: &str = r#"cmake_minimum_required(VERSION 3.20)
 project(
   {name}
   VERSION 0.1.0
   LANGUAGES CXX C
 )
-
-list(APPEND CMAKE_MODULE_PATH ${CMAKE_SOURCE_DIR}/cmake)
-
+{cmake_module_path}
 ### Options
 if(POLICY CMP0167)
   cmake_policy(SET CMP0167 NEW)
 endif()
 
-set(CMAKE_CXX_STANDARD 23)
+set(CMAKE_CXX_STANDARD {cxx_standard})
 set(CMAKE_CXX_STANDARD_REQUIRED ON)
 
 add_compile_options(-Wall -Wextra)
-
+{link_options}
 ### CPM
 set(CPM_DOWNLOAD_VERSION "{cpm_version}")
 set(CPM_HASH_SUM "{cpm_hash_sum}")
@@ -224,15 +316,54 @@ if (NOT EXISTS ${CPM_DOWNLOAD_LOCATION})
 endif()
 
 include(${CPM_DOWNLOAD_LOCATION})
-
+{cpm_package_lock}
 ### Library
-CPMAddPackage("gh:fmtlib/fmt#12.1.0")
+CPMAddPackage("gh:fmtlib/fmt#{fmt_version}")
 
 ### Executable
 add_executable({name} src/main.cc)
 target_link_libraries({name} PRIVATE fmt::fmt)
-target_compile_options({name} PRIVATE $<$<CONFIG:Debug>:-fsanitize=address,undefined>)
-target_link_options({name} PRIVATE $<$<CONFIG:Debug>:-fsanitize=address,undefined>)
+{sanitizers}"#;
+
+/// Spliced into [`CMAKE_LISTS`] when the project gets a `cmake/` directory.
+pub const CMAKE_MODULE_PATH: &str = "
+list(APPEND CMAKE_MODULE_PATH ${CMAKE_SOURCE_DIR}/cmake)
+";
+
+/// Spliced into [`CMAKE_LISTS`] unless `cmk new --no-lock`.
+pub const CPM_USE_PACKAGE_LOCK: &str = "CPMUsePackageLock(package-lock.cmake)\n";
+
+/// Initial `package-lock.cmake`. CPM lock files hold one `CPMDeclarePackage`
+/// per dependency; a declared version overrides the one `CPMAddPackage`
+/// asks for, so builds stay reproducible until the lock is updated with
+/// `cmake --build <build> --target cpm-update-package-lock`. Starts empty so
+/// the versions in CMakeLists.txt apply until then.
+pub const PACKAGE_LOCK: &str = "# CPM Package Lock
+# This file should be committed to version control
+";
+
+pub const FIND_EXAMPLE: &str = r#"# Starter find module. Copy it to Find<Name>.cmake, replace "Example" with
+# <Name> and the header/library names below, then `find_package(<Name>)`.
+#
+# Defines Example_FOUND and the imported target Example::Example.
+
+find_path(Example_INCLUDE_DIR NAMES example.h)
+find_library(Example_LIBRARY NAMES example)
+
+include(FindPackageHandleStandardArgs)
+find_package_handle_standard_args(Example
+  REQUIRED_VARS Example_LIBRARY Example_INCLUDE_DIR
+)
+
+if(Example_FOUND AND NOT TARGET Example::Example)
+  add_library(Example::Example UNKNOWN IMPORTED)
+  set_target_properties(Example::Example PROPERTIES
+    IMPORTED_LOCATION "${Example_LIBRARY}"
+    INTERFACE_INCLUDE_DIRECTORIES "${Example_INCLUDE_DIR}"
+  )
+endif()
+
+mark_as_advanced(Example_INCLUDE_DIR Example_LIBRARY)
 "#;
 
 pub const MAIN_CC: &str = r#"#include <fmt/format.h>
@@ -242,3 +373,23 @@ int main() {
     return 0;
 }
 "#;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sanitizer_options_are_debug_only() {
+        assert_eq!(sanitizer_options("app", &[]), "");
+        assert_eq!(
+            sanitizer_options("app", &ScaffoldOptions::default().sanitizers),
+            "target_compile_options(app PRIVATE $<$<CONFIG:Debug>:-fsanitize=address,undefined>)\n\
+             target_link_options(app PRIVATE $<$<CONFIG:Debug>:-fsanitize=address,undefined>)\n"
+        );
+        assert_eq!(
+            sanitizer_options("my-app", &["thread".to_string()]),
+            "target_compile_options(my-app PRIVATE $<$<CONFIG:Debug>:-fsanitize=thread>)\n\
+             target_link_options(my-app PRIVATE $<$<CONFIG:Debug>:-fsanitize=thread>)\n"
+        );
+    }
+}
@@ -0,0 +1,114 @@
+//! Target dependency graph in Graphviz DOT, for `cmk graph`.
+
+use std::collections::{BTreeSet, HashMap, VecDeque};
+
+use anyhow::{Result, anyhow};
+
+use crate::Target;
+
+/// Render `targets` and their `dependencies` as a `digraph`. With `root`,
+/// only that target and everything it transitively depends on is kept.
+/// Executables are drawn as boxes, everything else as ellipses.
+pub fn to_dot(targets: &[Target], root: Option<&str>) -> Result<String> {
+    let by_id: HashMap<&str, &Target> = targets.iter().map(|t| (t.id.as_str(), t)).collect();
+    let deps = |target: &Target| -> Vec<&Target> {
+        target
+            .dependencies
+            .iter()
+            .flatten()
+            .filter_map(|dep| by_id.get(dep.id.as_str()).copied())
+            .collect()
+    };
+
+    let selected: Vec<&Target> = match root {
+        None => targets.iter().collect(),
+        Some(name) => {
+            let root = targets
+                .iter()
+                .find(|t| t.name == name)
+                .ok_or_else(|| anyhow!("Target {name} not found"))?;
+            let mut seen = BTreeSet::from([root.id.as_str()]);
+            let mut queue = VecDeque::from([root]);
+            let mut selected = Vec::new();
+            while let Some(target) = queue.pop_front() {
+                selected.push(target);
+                for dep in deps(target) {
+                    if seen.insert(dep.id.as_str()) {
+                        queue.push_back(dep);
+                    }
+                }
+            }
+            selected
+        }
+    };
+
+    let mut nodes = BTreeSet::new();
+    let mut edges = BTreeSet::new();
+    for target in selected {
+        let shape = if target.is_executable() {
+            "box"
+        } else {
+            "ellipse"
+        };
+        nodes.insert(format!("  {} [shape={shape}];", quote(&target.name)));
+        for dep in deps(target) {
+            edges.insert(format!(
+                "  {} -> {};",
+                quote(&target.name),
+                quote(&dep.name)
+            ));
+        }
+    }
+
+    let mut dot = String::from("digraph targets {\n");
+    for line in nodes.iter().chain(&edges) {
+        dot.push_str(line);
+        dot.push('\n');
+    }
+    dot.push_str("}\n");
+    Ok(dot)
+}
+
+fn quote(name: &str) -> String {
+    format!("\"{}\"", name.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn target(name: &str, target_type: &str, deps: &[&str]) -> Target {
+        serde_json::from_value(serde_json::json!({
+            "name": name,
+            "id": format!("{name}::@0"),
+            "type": target_type,
+            "dependencies": deps
+                .iter()
+                .map(|d| serde_json::json!({ "id": format!("{d}::@0") }))
+                .collect::<Vec<_>>(),
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn root_keeps_only_its_transitive_dependencies() {
+        let targets = vec![
+            target("app", "EXECUTABLE", &["core"]),
+            target("core", "STATIC_LIBRARY", &["util"]),
+            target("util", "STATIC_LIBRARY", &[]),
+            target("bench", "EXECUTABLE", &["util"]),
+        ];
+        let dot = to_dot(&targets, Some("app")).unwrap();
+        assert_eq!(
+            dot,
+            "digraph targets {\n  \"app\" [shape=box];\n  \"core\" [shape=ellipse];\n  \
+             \"util\" [shape=ellipse];\n  \"app\" -> \"core\";\n  \"core\" -> \"util\";\n}\n"
+        );
+        assert!(
+            to_dot(&targets, None)
+                .unwrap()
+                .contains("\"bench\" -> \"util\"")
+        );
+        assert!(to_dot(&targets, Some("missing")).is_err());
+    }
+}
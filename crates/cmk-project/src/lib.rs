@@ -1,6 +1,73 @@
 pub mod cmake;
 pub mod cmake_ast;
 pub mod default;
+pub mod graph;
+mod ninja;
 
-pub use cmake::{CMakeProject, Target, get_project_root};
-pub use default::{Template, load_template};
+pub use cmake::{
+    BUILD_DIR_ENV, BuildDirInfo, BuildOutput, CMakeProject, CompDBEntry, ConfigureResult,
+    PROJECT_ROOT_ENV, RUN_EXIT_CODE_ENV, StdinMode, Target, TargetCache, TargetDependency,
+    TargetFolder, TargetSource, get_project_root, is_cmake_true, is_ninja_build_dir,
+    read_all_cache_variables, read_cache_variable, require_git, set_project_root,
+};
+pub use default::{ScaffoldOptions, Template, load_template};
+
+use std::path::PathBuf;
+
+/// Why cmk can't work on the current project at all. `main` follows these
+/// with a hint on how to fix the setup.
+#[derive(Debug, thiserror::Error)]
+pub enum CmkError {
+    #[error("Not inside a git repository; cmk uses the repository root as the project root")]
+    NoGitRepository,
+    #[error("No CMake build directories found under {}", .0.display())]
+    NoBuildDirectory(PathBuf),
+    #[error("git is not installed or not on PATH; cmk needs it to find the project root")]
+    GitNotInstalled,
+    #[error("cmake is not installed or not on PATH")]
+    CmakeNotInstalled,
+}
+
+/// Parallel build jobs: `explicit` (`--jobs`), else `CMK_DEFAULT_JOBS`, else
+/// `CMAKE_BUILD_PARALLEL_LEVEL`, else one less than the available cores.
+pub fn resolve_job_count(explicit: Option<usize>) -> usize {
+    let env = |name| std::env::var(name).ok();
+    let cores = std::thread::available_parallelism().map_or(2, |n| n.get());
+    job_count(
+        explicit,
+        env("CMK_DEFAULT_JOBS").as_deref(),
+        env("CMAKE_BUILD_PARALLEL_LEVEL").as_deref(),
+        cores,
+    )
+}
+
+/// [`resolve_job_count`] with its inputs passed in. Unparsable or zero
+/// values are skipped.
+fn job_count(
+    explicit: Option<usize>,
+    cmk_default: Option<&str>,
+    parallel_level: Option<&str>,
+    cores: usize,
+) -> usize {
+    let parse = |v: Option<&str>| v.and_then(|s| s.trim().parse::<usize>().ok());
+    explicit
+        .into_iter()
+        .chain(parse(cmk_default))
+        .chain(parse(parallel_level))
+        .find(|&jobs| jobs > 0)
+        .unwrap_or_else(|| cores.saturating_sub(1).max(1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn job_count_precedence() {
+        assert_eq!(job_count(Some(3), Some("5"), Some("7"), 16), 3);
+        assert_eq!(job_count(None, Some("5"), Some("7"), 16), 5);
+        assert_eq!(job_count(None, None, Some("7"), 16), 7);
+        assert_eq!(job_count(None, Some("x"), Some("0"), 16), 15);
+        assert_eq!(job_count(None, None, None, 1), 1);
+    }
+}
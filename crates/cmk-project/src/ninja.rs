@@ -0,0 +1,284 @@
+//! Readers for Ninja's on-disk state in a build dir (`.ninja_log`,
+//! `.ninja_deps`, `build.ninja`'s regeneration edge), used for cheap
+//! staleness checks that avoid spawning `cmake --build` when nothing
+//! changed, and for its `[current/total]` status lines, which drive
+//! `cmk build --progress`.
+
+use anyhow::{Result, anyhow};
+use indicatif::{ProgressBar, ProgressStyle};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader};
+
+/// Status line format cmk asks Ninja for, so [`parse_status`] does not
+/// depend on the user's own `NINJA_STATUS`.
+pub(crate) const NINJA_STATUS: &str = "[%f/%t] ";
+
+/// Parse `.ninja_log` into `output -> recorded mtime`. Later entries for the
+/// same output win, matching Ninja's own replay semantics.
+///
+/// v5+ lines are `<start>\t<end>\t<mtime>\t<output>\t<hash>`; the mtime and
+/// output columns are what we care about.
+pub(crate) fn parse_ninja_log(content: &str) -> HashMap<String, u64> {
+    let mut entries = HashMap::new();
+    for line in content.lines() {
+        if line.starts_with('#') {
+            continue;
+        }
+        let fields: Vec<&str> = line.split('\t').collect();
+        if fields.len() < 4 {
+            continue;
+        }
+        if let Ok(mtime) = fields[2].parse::<u64>() {
+            entries.insert(fields[3].to_string(), mtime);
+        }
+    }
+    entries
+}
+
+/// Every path that appears as an input of some deps record in `.ninja_deps`
+/// (headers included). Relative paths are resolved against `build_dir`.
+pub(crate) fn read_ninja_deps_inputs(build_dir: &Path) -> Result<Vec<PathBuf>> {
+    let data = std::fs::read(build_dir.join(".ninja_deps"))?;
+    const MAGIC: &[u8] = b"# ninjadeps\n";
+    if !data.starts_with(MAGIC) || data.len() < MAGIC.len() + 4 {
+        return Err(anyhow!("Unrecognized .ninja_deps header"));
+    }
+    let read_u32 = |at: usize| -> Option<u32> {
+        data.get(at..at + 4)
+            .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    };
+    let version = read_u32(MAGIC.len()).unwrap_or(0);
+    // v3 stores a 4-byte mtime, v4 an 8-byte one.
+    let mtime_len = match version {
+        3 => 4,
+        4 => 8,
+        v => return Err(anyhow!("Unsupported .ninja_deps version {v}")),
+    };
+
+    let mut paths: Vec<String> = Vec::new();
+    let mut input_ids: Vec<usize> = Vec::new();
+    let mut pos = MAGIC.len() + 4;
+    while let Some(header) = read_u32(pos) {
+        pos += 4;
+        let is_deps = header & 0x8000_0000 != 0;
+        let size = (header & 0x7fff_ffff) as usize;
+        let Some(record) = data.get(pos..pos + size) else {
+            // Truncated trailing record (ninja was interrupted); ignore it.
+            break;
+        };
+        pos += size;
+        if is_deps {
+            let start = 4 + mtime_len;
+            input_ids.extend(
+                record
+                    .get(start..)
+                    .unwrap_or_default()
+                    .chunks_exact(4)
+                    .map(|b| i32::from_le_bytes([b[0], b[1], b[2], b[3]]) as usize),
+            );
+        } else {
+            let name = &record[..size.saturating_sub(4)];
+            let end = name.iter().position(|&b| b == 0).unwrap_or(name.len());
+            paths.push(String::from_utf8_lossy(&name[..end]).into_owned());
+        }
+    }
+
+    input_ids.sort_unstable();
+    input_ids.dedup();
+    Ok(input_ids
+        .into_iter()
+        .filter_map(|id| paths.get(id))
+        .map(|p| build_dir.join(p))
+        .collect())
+}
+
+/// Inputs of the `RERUN_CMAKE` edge in `build_dir`'s `build.ninja`: every
+/// `CMakeLists.txt` and included `.cmake` file whose change makes Ninja
+/// rerun CMake. Relative paths are resolved against `build_dir`.
+pub(crate) fn read_regeneration_inputs(build_dir: &Path) -> Result<Vec<PathBuf>> {
+    let content = std::fs::read_to_string(build_dir.join("build.ninja"))?;
+    Ok(parse_regeneration_inputs(&content)
+        .into_iter()
+        .map(|p| build_dir.join(p))
+        .collect())
+}
+
+/// The implicit inputs (`| ...`) of the first `RERUN_CMAKE` build statement
+/// in `content`, with `$`-escapes undone.
+fn parse_regeneration_inputs(content: &str) -> Vec<String> {
+    let mut statement = String::new();
+    for line in content.lines() {
+        if statement.is_empty() && !line.starts_with("build ") {
+            continue;
+        }
+        let line = line.trim_start();
+        if line.ends_with('$') && !line.ends_with("$$") {
+            statement.push_str(&line[..line.len() - 1]);
+            statement.push(' ');
+            continue;
+        }
+        statement.push_str(line);
+        let words = split_ninja_words(&statement);
+        statement.clear();
+        let Some(rule) = words.iter().position(|w| w == "RERUN_CMAKE") else {
+            continue;
+        };
+        return words[rule + 1..]
+            .iter()
+            .skip_while(|w| *w != "|")
+            .skip(1)
+            .take_while(|w| !w.starts_with("||"))
+            .cloned()
+            .collect();
+    }
+    Vec::new()
+}
+
+/// Split a Ninja statement on unescaped whitespace, turning `$ `, `$:` and
+/// `$$` back into the characters they stand for.
+fn split_ninja_words(statement: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut chars = statement.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '$' => match chars.next() {
+                Some(e @ (' ' | ':' | '$')) => word.push(e),
+                Some(e) => {
+                    word.push('$');
+                    word.push(e);
+                }
+                None => {}
+            },
+            ' ' => {
+                if !word.is_empty() {
+                    words.push(std::mem::take(&mut word));
+                }
+            }
+            c => word.push(c),
+        }
+    }
+    if !word.is_empty() {
+        words.push(word);
+    }
+    words
+}
+
+pub(crate) fn mtime(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// Split a status line such as `[3/120] Building CXX object foo.o` into
+/// `(3, 120, "Building CXX object foo.o")`. Anything else (compiler output,
+/// `ninja: ...` messages) yields `None`.
+pub(crate) fn parse_status(line: &str) -> Option<(u64, u64, &str)> {
+    let (counts, description) = line.strip_prefix('[')?.split_once("] ")?;
+    let (current, total) = counts.split_once('/')?;
+    Some((current.parse().ok()?, total.parse().ok()?, description))
+}
+
+/// Drive a progress bar from Ninja's stdout until it closes. Lines that are
+/// not status lines are forwarded above the bar, or straight to stdout when
+/// the bar is hidden because stderr is not a terminal.
+pub(crate) async fn report_progress(output: impl AsyncRead + Unpin) -> Result<()> {
+    let bar = ProgressBar::new(0);
+    bar.set_style(ProgressStyle::with_template(
+        "[{pos}/{len}] {bar:30} {percent:>3}% {wide_msg}",
+    )?);
+    let mut lines = BufReader::new(output).lines();
+    while let Some(line) = lines.next_line().await? {
+        match parse_status(&line) {
+            Some((current, total, description)) => {
+                bar.set_length(total);
+                bar.set_position(current);
+                bar.set_message(description.to_string());
+            }
+            None if bar.is_hidden() => println!("{line}"),
+            None => bar.println(line),
+        }
+    }
+    bar.finish_and_clear();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ninja_log_keeps_latest_entry() {
+        let log = "# ninja log v5\n\
+                   0\t10\t100\tfoo\tabc\n\
+                   5\t12\t200\tbar.o\tdef\n\
+                   20\t30\t300\tfoo\tabc\n";
+        let entries = parse_ninja_log(log);
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries["foo"], 300);
+        assert_eq!(entries["bar.o"], 200);
+    }
+
+    #[test]
+    fn ninja_deps_lists_inputs() {
+        fn path_record(out: &mut Vec<u8>, name: &str, id: u32) {
+            let mut bytes = name.as_bytes().to_vec();
+            while !bytes.len().is_multiple_of(4) {
+                bytes.push(0);
+            }
+            out.extend(((bytes.len() + 4) as u32).to_le_bytes());
+            out.extend(bytes);
+            out.extend((!id).to_le_bytes());
+        }
+        let mut data = b"# ninjadeps\n".to_vec();
+        data.extend(4u32.to_le_bytes());
+        path_record(&mut data, "a.o", 0);
+        path_record(&mut data, "a.cc", 1);
+        path_record(&mut data, "a.h", 2);
+        let deps: [u32; 5] = [0, 7, 0, 1, 2];
+        data.extend((0x8000_0000u32 | 20).to_le_bytes());
+        for v in deps {
+            data.extend(v.to_le_bytes());
+        }
+
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join(".ninja_deps"), &data).unwrap();
+        let inputs = read_ninja_deps_inputs(dir.path()).unwrap();
+        assert_eq!(
+            inputs,
+            vec![dir.path().join("a.cc"), dir.path().join("a.h")]
+        );
+    }
+
+    #[test]
+    fn regeneration_inputs_follow_rerun_cmake() {
+        let build_ninja = "# CMAKE generated file: DO NOT EDIT!\n\
+                           build all: phony app\n\
+                           build build.ninja /b/cmake_install.cmake: RERUN_CMAKE | \
+                           /s/CMakeLists.txt /s/src/CMakeLists.txt $\n    \
+                           /s/cmake/My$ Utils.cmake || cmake_object_order\n\
+                           build /s/src/CMakeLists.txt: phony\n";
+        assert_eq!(
+            parse_regeneration_inputs(build_ninja),
+            vec![
+                "/s/CMakeLists.txt",
+                "/s/src/CMakeLists.txt",
+                "/s/cmake/My Utils.cmake"
+            ]
+        );
+        assert!(parse_regeneration_inputs("build all: phony app\n").is_empty());
+    }
+
+    #[test]
+    fn status_lines_are_parsed() {
+        assert_eq!(
+            parse_status("[3/120] Building CXX object src/foo.cc.o"),
+            Some((3, 120, "Building CXX object src/foo.cc.o"))
+        );
+        assert_eq!(parse_status("[1/1] "), Some((1, 1, "")));
+        assert_eq!(parse_status("ninja: no work to do."), None);
+        assert_eq!(parse_status("[-Wunused-variable] x"), None);
+    }
+}